@@ -0,0 +1,23 @@
+#import bevy_pbr::{
+    mesh_view_bindings::view,
+    forward_io::VertexOutput,
+}
+
+@group(2) @binding(0) var<uniform> material_color: vec4<f32>;
+@group(2) @binding(1) var material_color_texture: texture_2d<f32>;
+@group(2) @binding(2) var material_color_sampler: sampler;
+// fadeNear and fadeFar. Off when fadeFar isn't positive.
+@group(2) @binding(3) var<uniform> fade: vec2<f32>;
+
+@fragment
+fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
+  var color = textureSample(material_color_texture, material_color_sampler, mesh.uv) * material_color;
+
+  if fade.y > 0.0 {
+    let near = clamp(fade.x, 0.0, fade.y);
+    let dist = distance(mesh.world_position.xyz, view.world_position);
+    color.a *= 1.0 - clamp((dist - near) / max(fade.y - near, 0.0001), 0.0, 1.0);
+  }
+
+  return color;
+}
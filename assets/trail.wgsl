@@ -1,6 +1,6 @@
 // The time since startup data is in the globals binding which is part of the mesh_view_bindings import
 #import bevy_pbr::{
-    mesh_view_bindings::globals,
+    mesh_view_bindings::{globals, view},
     forward_io::VertexOutput,
 }
 
@@ -8,12 +8,22 @@
 @group(2) @binding(1) var material_color_texture: texture_2d<f32>;
 @group(2) @binding(2) var material_color_sampler: sampler;
 @group(2) @binding(3) var<uniform> speed: f32;
+// fadeNear and fadeFar. Off when fadeFar isn't positive.
+@group(2) @binding(4) var<uniform> fade: vec2<f32>;
 
 @fragment
 fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
-  return textureSample(
+  var color = textureSample(
            material_color_texture,
            material_color_sampler,
            vec2(mesh.uv.x, mesh.uv.y + globals.time * speed)
         ) * material_color;
+
+  if fade.y > 0.0 {
+    let near = clamp(fade.x, 0.0, fade.y);
+    let dist = distance(mesh.world_position.xyz, view.world_position);
+    color.a *= 1.0 - clamp((dist - near) / max(fade.y - near, 0.0001), 0.0, 1.0);
+  }
+
+  return color;
 }
@@ -1,4 +1,4 @@
-/// Input systems shared between both the overlay and link applications.
+//! Input systems shared between both the overlay and link applications.
 
 use bevy::{input::ButtonState, prelude::*};
 
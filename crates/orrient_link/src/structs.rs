@@ -128,7 +128,7 @@ struct Identity {
     pub uisz: u8,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Profession {
     Unknown = 0,
     Guardian = 1,
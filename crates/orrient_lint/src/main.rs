@@ -0,0 +1,64 @@
+//! Check marker packs for problems without starting the overlay.
+//!
+//! Prints every diagnostic found while parsing and exits with a
+//! non-zero status if any pack has errors, so it can be run before
+//! publishing a pack.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use orrient_pathing::prelude::*;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Marker pack archives or unpacked pack directories
+    #[arg(required = true)]
+    packs: Vec<PathBuf>,
+
+    /// Fail on warnings as well as errors
+    #[arg(long)]
+    deny_warnings: bool,
+
+    /// Only print errors
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let mut failed = false;
+    for path in &args.packs {
+        let pack = match read_marker_pack(path) {
+            Ok(pack) => pack,
+            Err(err) => {
+                eprintln!("{}: {err:#}", path.display());
+                failed = true;
+                continue;
+            }
+        };
+
+        let diagnostics = pack.diagnostics();
+        for diagnostic in diagnostics.iter() {
+            if args.quiet && diagnostic.severity == Severity::Warning {
+                continue;
+            }
+            println!("{}: {diagnostic}", pack.id());
+        }
+
+        let errors = diagnostics.errors().count();
+        let warnings = diagnostics.warnings().count();
+        eprintln!("{}: {errors} errors, {warnings} warnings", pack.id());
+        if errors > 0 || (args.deny_warnings && warnings > 0) {
+            failed = true;
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -7,13 +7,31 @@ use bevy::prelude::*;
 pub mod prelude {
     pub use crate::events::MarkerEvent;
     pub use crate::events::ReloadMarkersEvent;
+    pub use crate::marker::behavior::PlayerContext;
+    pub use crate::marker::behavior::UsedPois;
+    pub use crate::marker::poi::PoiMapIcon;
     pub use crate::marker::poi::PoiMarker;
+    pub use crate::marker::spatial::Nearby;
+    pub use crate::marker::spatial::SpatialEntry;
+    pub use crate::marker::spatial::SpatialIndex;
+    pub use crate::marker::spatial::SpatialKind;
     pub use crate::marker::trail::create_trail_mesh;
+    pub use crate::marker::trail::TrailJoin;
     pub use crate::marker::trail::TrailMaterial;
     pub use crate::marker::trail::TrailMesh;
+    pub use crate::marker::trail::TrailMeshOptions;
+    pub use crate::marker::trail::TrailSettings;
+    pub use crate::marker::visibility::FadeSettings;
     pub use crate::marker::EnabledMarkers;
     pub use crate::marker::MapMarkers;
+    pub use crate::parser::diagnostics::Diagnostic;
+    pub use crate::parser::diagnostics::DiagnosticKind;
+    pub use crate::parser::diagnostics::PackDiagnostics;
+    pub use crate::parser::diagnostics::Severity;
+    pub use crate::parser::export::write_taco;
+    pub use crate::parser::export::write_xml;
     pub use crate::parser::model::Behavior;
+    pub use crate::parser::model::DisplayAttributes;
     pub use crate::parser::model::MarkerKind;
     pub use crate::parser::pack::FullMarkerId;
     pub use crate::parser::pack::Marker;
@@ -21,9 +39,17 @@ pub mod prelude {
     pub use crate::parser::pack::MarkerName;
     pub use crate::parser::pack::MarkerPack;
     pub use crate::parser::pack::MarkerPath;
+    pub use crate::parser::pack::MergedCategory;
     pub use crate::parser::pack::Poi;
+    pub use crate::parser::pack::PoiGuid;
+    pub use crate::parser::read_marker_pack;
+    pub use crate::parser::textures::PackTextures;
+    pub use crate::parser::trail;
+    pub use crate::parser::trail::TrailData;
     pub use crate::parser::MarkerPacks;
     pub use crate::parser::PackId;
+    pub use crate::parser::PackLoadProgress;
+    pub use crate::parser::PackLoadStatus;
 }
 
 pub struct Plugin;
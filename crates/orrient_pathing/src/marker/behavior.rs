@@ -0,0 +1,411 @@
+use orrient_core::prelude::*;
+use orrient_link::SocketMessage;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use ron::ser::PrettyConfig;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use super::output_error;
+use super::state_dir;
+use crate::parser::filter::FilterContext;
+use crate::parser::model::Behavior;
+use crate::parser::pack::FullMarkerId;
+use crate::parser::pack::PoiGuid;
+
+/// Length of a day in Guild Wars 2. The daily reset happens at 00:00
+/// UTC.
+const DAY: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// The map instance the player is currently in, as reported by
+/// MumbleLink.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MapInstance {
+    pub map_id: u32,
+    pub shard_id: u32,
+    pub instance: u32,
+}
+
+/// The state of the player needed to decide whether a used POI should
+/// reappear.
+#[derive(Resource, Clone, Default, Debug, PartialEq)]
+pub struct PlayerContext {
+    pub character: String,
+    pub map: MapInstance,
+}
+
+#[derive(Hash, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsedPoiKey {
+    pub full_id: FullMarkerId,
+    pub guid: PoiGuid,
+    /// Only set for [`Behavior::ReappearDailyPerCharacter`] so that
+    /// each character tracks its own POIs.
+    pub character: Option<String>,
+}
+
+impl UsedPoiKey {
+    fn new(
+        full_id: FullMarkerId,
+        guid: PoiGuid,
+        behavior: Behavior,
+        context: &PlayerContext,
+    ) -> Self {
+        Self {
+            full_id,
+            guid,
+            character: match behavior {
+                Behavior::ReappearDailyPerCharacter => Some(context.character.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UsedPoi {
+    pub behavior: Behavior,
+    pub used_at: SystemTime,
+    pub character: String,
+    pub map: MapInstance,
+}
+
+impl UsedPoi {
+    /// Returns true while the POI should stay hidden.
+    fn is_active(&self, context: &PlayerContext, now: SystemTime) -> bool {
+        match self.behavior {
+            Behavior::AlwaysVisible => false,
+            Behavior::ReappearOnMapChange => self.map == context.map,
+            Behavior::ReappearDaily | Behavior::ReappearDailyPerCharacter => {
+                day_of(self.used_at) == day_of(now)
+            }
+            Behavior::DisappearOnUse => true,
+            Behavior::ReappearAfterTime(reset_length) => now
+                .duration_since(self.used_at)
+                .map(|elapsed| elapsed.as_secs_f32() < reset_length)
+                .unwrap_or(true),
+            // MumbleLink doesn't report when a map's meta cycle resets,
+            // so this is deliberately approximated. A map reset always
+            // puts the player into a fresh instance, so map resets are
+            // tracked like instance changes, and may reappear early when
+            // the player hops instances. Only expire when we're back on
+            // the same map, but in another instance.
+            Behavior::ReappearMapReset | Behavior::ReappearInstanceChange => {
+                self.map.map_id != context.map.map_id || self.map == context.map
+            }
+        }
+    }
+}
+
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / DAY.as_secs()
+}
+
+/// POIs that have been used by the player, persisted between
+/// sessions.
+#[derive(Resource, Clone, Deref, DerefMut, Debug, Default, Serialize, Deserialize)]
+pub struct UsedPois(pub HashMap<UsedPoiKey, UsedPoi>);
+
+impl UsedPois {
+    /// Returns true if the POI was used and its [`Behavior`] says it
+    /// should still be hidden.
+    pub fn is_hidden(
+        &self,
+        full_id: &FullMarkerId,
+        guid: PoiGuid,
+        behavior: Behavior,
+        context: &PlayerContext,
+        now: SystemTime,
+    ) -> bool {
+        self.get(&UsedPoiKey::new(full_id.clone(), guid, behavior, context))
+            .is_some_and(|used| used.is_active(context, now))
+    }
+
+    /// Record that the player used a POI.
+    pub fn use_poi(
+        &mut self,
+        full_id: FullMarkerId,
+        guid: PoiGuid,
+        behavior: Behavior,
+        context: &PlayerContext,
+        now: SystemTime,
+    ) {
+        if let Behavior::AlwaysVisible = behavior {
+            return;
+        }
+
+        self.insert(
+            UsedPoiKey::new(full_id, guid, behavior, context),
+            UsedPoi {
+                behavior,
+                used_at: now,
+                character: context.character.clone(),
+                map: context.map,
+            },
+        );
+    }
+
+    /// Forget every POI that is allowed to reappear.
+    pub fn prune(&mut self, context: &PlayerContext, now: SystemTime) {
+        self.retain(|_key, used| used.is_active(context, now));
+    }
+}
+
+fn track_context_system(
+    mut events: EventReader<SocketMessage>,
+    mut context: ResMut<PlayerContext>,
+    mut filter_context: ResMut<FilterContext>,
+) {
+    for event in events.read() {
+        let SocketMessage::MumbleLinkData(data) = event else {
+            continue;
+        };
+
+        context.set_if_neq(PlayerContext {
+            character: data.identity.name.clone(),
+            map: MapInstance {
+                map_id: data.context.map_id,
+                shard_id: data.context.shard_id,
+                instance: data.context.instance,
+            },
+        });
+        filter_context.set_if_neq(FilterContext::new(&data.identity, &data.context));
+    }
+}
+
+fn prune_system(mut used_pois: ResMut<UsedPois>, context: Res<PlayerContext>) {
+    // Nothing is known about the player until MumbleLink reports in.
+    if *context == PlayerContext::default() {
+        return;
+    }
+
+    let now = SystemTime::now();
+    // Only mark the resource changed, and so saved, if something reappears.
+    let pruned = used_pois
+        .bypass_change_detection()
+        .values()
+        .filter(|used| !used.is_active(&context, now))
+        .count();
+    if pruned > 0 {
+        used_pois.prune(&context, now);
+        info!("{pruned} used POI(s) reappeared.");
+    }
+}
+
+fn find_used_file() -> Result<PathBuf> {
+    Ok(state_dir()?.join("used.ron"))
+}
+
+fn load_system(filepath: In<Result<PathBuf>>, mut commands: Commands) -> Result<()> {
+    let filepath = filepath.0?;
+
+    if !std::fs::exists(&filepath).unwrap_or_default() {
+        commands.init_resource::<UsedPois>();
+        return Ok(());
+    }
+
+    let data =
+        File::open(&filepath).map_err(|err| anyhow!("Could not read {filepath:?}: {err:?}"))?;
+
+    let used_pois: UsedPois = ron::de::from_reader(data)
+        .map_err(|err| anyhow!("Could not deserialize {filepath:?}: {err:?}"))?;
+
+    commands.insert_resource(used_pois);
+    Ok(())
+}
+
+fn save_system(filepath: In<Result<PathBuf>>, used_pois: Res<UsedPois>) -> Result<()> {
+    let filepath = filepath.0?;
+    debug!("Saving used POIs to {filepath:?}");
+
+    let data = ron::ser::to_string_pretty(&*used_pois, PrettyConfig::default())
+        .map_err(|err| anyhow!("Could not serialize used POIs: {err:?}"))?;
+
+    let mut file = File::create(&filepath)
+        .map_err(|err| anyhow!("Could not write to state file when trying to save: {err:?}"))?;
+
+    file.write_all(data.as_bytes())
+        .map_err(|err| anyhow!("Could not write to {filepath:?}: {err:?}"))?;
+
+    Ok(())
+}
+
+pub(super) struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SocketMessage>();
+        app.init_resource::<PlayerContext>();
+        app.init_resource::<FilterContext>();
+        app.init_resource::<UsedPois>();
+
+        app.add_systems(Startup, find_used_file.pipe(load_system).pipe(output_error));
+        app.add_systems(
+            Update,
+            find_used_file
+                .pipe(save_system)
+                .pipe(output_error)
+                .run_if(not(in_state(AppState::ParsingMarkerPacks)))
+                .run_if(resource_exists_and_changed::<UsedPois>),
+        );
+        app.add_systems(
+            Update,
+            (
+                track_context_system.run_if(on_event::<SocketMessage>()),
+                prune_system.run_if(resource_changed::<PlayerContext>),
+            )
+                .chain(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parser::pack::MarkerName;
+    use crate::parser::PackId;
+
+    const GUID: PoiGuid = PoiGuid([0; 16]);
+
+    fn full_id() -> FullMarkerId {
+        PackId("pack".into()).with_marker(MarkerName(vec!["a".into()]))
+    }
+
+    fn context(character: &str, map_id: u32, instance: u32) -> PlayerContext {
+        PlayerContext {
+            character: character.into(),
+            map: MapInstance {
+                map_id,
+                shard_id: 0,
+                instance,
+            },
+        }
+    }
+
+    #[test]
+    fn test_daily() {
+        let mut used = UsedPois::default();
+        let context = context("a", 15, 1);
+        let now = SystemTime::UNIX_EPOCH + DAY * 100 + Duration::from_secs(60);
+        used.use_poi(full_id(), GUID, Behavior::ReappearDaily, &context, now);
+
+        let later = now + Duration::from_secs(60 * 60);
+        assert!(used.is_hidden(&full_id(), GUID, Behavior::ReappearDaily, &context, later));
+        let tomorrow = now + DAY;
+        assert!(!used.is_hidden(
+            &full_id(),
+            GUID,
+            Behavior::ReappearDaily,
+            &context,
+            tomorrow
+        ));
+    }
+
+    #[test]
+    fn test_daily_per_character() {
+        let mut used = UsedPois::default();
+        let behavior = Behavior::ReappearDailyPerCharacter;
+        let now = SystemTime::UNIX_EPOCH + DAY * 100;
+        used.use_poi(full_id(), GUID, behavior, &context("a", 15, 1), now);
+
+        assert!(used.is_hidden(&full_id(), GUID, behavior, &context("a", 15, 1), now));
+        assert!(!used.is_hidden(&full_id(), GUID, behavior, &context("b", 15, 1), now));
+    }
+
+    #[test]
+    fn test_after_time() {
+        let mut used = UsedPois::default();
+        let behavior = Behavior::ReappearAfterTime(60.0);
+        let context = context("a", 15, 1);
+        let now = SystemTime::UNIX_EPOCH + DAY;
+        used.use_poi(full_id(), GUID, behavior, &context, now);
+
+        let later = now + Duration::from_secs(30);
+        assert!(used.is_hidden(&full_id(), GUID, behavior, &context, later));
+        let later = now + Duration::from_secs(90);
+        assert!(!used.is_hidden(&full_id(), GUID, behavior, &context, later));
+    }
+
+    #[test]
+    fn test_instance_change() {
+        let mut used = UsedPois::default();
+        let behavior = Behavior::ReappearInstanceChange;
+        let now = SystemTime::UNIX_EPOCH + DAY;
+        used.use_poi(full_id(), GUID, behavior, &context("a", 15, 1), now);
+
+        used.prune(&context("a", 50, 1), now);
+        assert!(used.is_hidden(&full_id(), GUID, behavior, &context("a", 15, 1), now));
+        used.prune(&context("a", 15, 2), now);
+        assert!(used.is_empty());
+    }
+
+    #[test]
+    fn test_map_reset() {
+        let mut used = UsedPois::default();
+        let behavior = Behavior::ReappearMapReset;
+        let now = SystemTime::UNIX_EPOCH + DAY;
+        used.use_poi(full_id(), GUID, behavior, &context("a", 15, 1), now);
+
+        // Approximated as an instance change.
+        used.prune(&context("a", 50, 1), now);
+        assert!(used.is_hidden(&full_id(), GUID, behavior, &context("a", 15, 1), now));
+        used.prune(&context("a", 15, 2), now);
+        assert!(used.is_empty());
+    }
+
+    #[test]
+    fn test_prune_on_context_change() {
+        let mut app = App::new();
+        app.init_resource::<PlayerContext>();
+        app.init_resource::<UsedPois>();
+        app.add_systems(
+            Update,
+            prune_system.run_if(resource_changed::<PlayerContext>),
+        );
+
+        let behavior = Behavior::ReappearOnMapChange;
+        let now = SystemTime::now();
+        app.world_mut().resource_mut::<UsedPois>().use_poi(
+            full_id(),
+            GUID,
+            behavior,
+            &context("a", 15, 1),
+            now,
+        );
+
+        // Before MumbleLink reports in, nothing is pruned.
+        app.update();
+        assert_eq!(app.world().resource::<UsedPois>().len(), 1);
+
+        *app.world_mut().resource_mut::<PlayerContext>() = context("a", 15, 1);
+        app.update();
+        assert_eq!(app.world().resource::<UsedPois>().len(), 1);
+
+        *app.world_mut().resource_mut::<PlayerContext>() = context("a", 50, 1);
+        app.update();
+        assert!(app.world().resource::<UsedPois>().is_empty());
+    }
+
+    #[test]
+    fn test_map_change() {
+        let mut used = UsedPois::default();
+        let behavior = Behavior::ReappearOnMapChange;
+        let now = SystemTime::UNIX_EPOCH + DAY;
+        used.use_poi(full_id(), GUID, behavior, &context("a", 15, 1), now);
+
+        assert!(used.is_hidden(&full_id(), GUID, behavior, &context("a", 15, 1), now));
+        used.prune(&context("a", 50, 1), now);
+        assert!(used.is_empty());
+    }
+}
@@ -1,5 +1,8 @@
+pub mod behavior;
 pub mod poi;
+pub mod spatial;
 pub mod trail;
+pub mod visibility;
 
 use crate::events::MarkerEvent;
 use crate::parser::pack::FullMarkerId;
@@ -57,7 +60,7 @@ fn track_markers_system(
     }
 }
 
-fn find_enabled_file() -> Result<PathBuf> {
+fn state_dir() -> Result<PathBuf> {
     let base_dirs = BaseDirs::new().ok_or(anyhow!("Could not find base directories"))?;
 
     let state_dir = base_dirs
@@ -68,7 +71,11 @@ fn find_enabled_file() -> Result<PathBuf> {
     std::fs::create_dir_all(&dir)
         .map_err(|err| anyhow!("Could not create directory {dir:?}: {err:?}"))?;
 
-    Ok(dir.join("enabled.ron"))
+    Ok(dir)
+}
+
+fn find_enabled_file() -> Result<PathBuf> {
+    Ok(state_dir()?.join("enabled.ron"))
 }
 
 fn load_system(filepath: In<Result<PathBuf>>, mut commands: Commands) -> Result<()> {
@@ -118,8 +125,11 @@ impl bevy::prelude::Plugin for Plugin {
         app.init_resource::<MapMarkers>();
         app.init_resource::<EnabledMarkers>();
 
+        app.add_plugins(behavior::Plugin);
         app.add_plugins(poi::Plugin);
+        app.add_plugins(spatial::Plugin);
         app.add_plugins(trail::Plugin);
+        app.add_plugins(visibility::Plugin);
 
         app.add_systems(OnEnter(GameState::ChangingMaps), map_exit_system);
         app.add_systems(OnEnter(GameState::InGame), map_enter_system);
@@ -1,17 +1,44 @@
+use bevy::ecs::system::SystemParam;
+use bevy::pbr::MaterialPipeline;
+use bevy::pbr::MaterialPipelineKey;
 use bevy::prelude::*;
+use bevy::render::mesh::MeshVertexBufferLayoutRef;
+use bevy::render::render_resource::AsBindGroup;
+use bevy::render::render_resource::RenderPipelineDescriptor;
+use bevy::render::render_resource::ShaderRef;
+use bevy::render::render_resource::SpecializedMeshPipelineError;
+use bevy::utils::HashMap;
+use bevy::utils::HashSet;
 use orrient_core::prelude::*;
 
+use super::behavior::PlayerContext;
+use super::behavior::UsedPois;
+use super::spatial::SpatialEntry;
+use super::spatial::SpatialIndex;
+use super::spatial::SpatialKind;
+use super::visibility::Fade;
+use super::visibility::FadeSettings;
 use super::EnabledMarkers;
 use crate::events::MarkerEvent;
+use crate::parser::filter::FilterContext;
 use crate::parser::model::Behavior;
+use crate::parser::model::DisplayAttributes;
+use crate::parser::pack::FullMarkerId;
+use crate::parser::pack::Poi;
+use crate::parser::pack::PoiGuid;
+use crate::parser::textures::PackTextures;
 use crate::parser::MarkerPacks;
-use crate::prelude::FullMarkerId;
 
 use bevy_mod_billboard::plugin::BillboardPlugin;
-use bevy_mod_billboard::BillboardMeshHandle;
 use bevy_mod_billboard::BillboardTextBundle;
-use bevy_mod_billboard::BillboardTextureBundle;
-use bevy_mod_billboard::BillboardTextureHandle;
+
+use std::time::SystemTime;
+
+// TacO's defaults for unset `DisplayAttributes`.
+const DEFAULT_HEIGHT_OFFSET: f32 = 1.5;
+const DEFAULT_MIN_SIZE: f32 = 5.0;
+const DEFAULT_MAX_SIZE: f32 = 2048.0;
+const DEFAULT_MAP_DISPLAY_SIZE: f32 = 20.0;
 
 fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, asset_server: Res<AssetServer>) {
     commands.insert_resource(PoiQuad(meshes.add(Rectangle::from_size(Vec2::splat(2.0)))));
@@ -24,17 +51,196 @@ struct MissingIcon(Handle<Image>);
 #[derive(Component)]
 pub struct PoiMarker;
 
+/// The icon of a POI in the world.
+#[derive(Component)]
+pub struct PoiIcon {
+    /// Scale of the icon in the world.
+    size: f32,
+    /// Smallest size of the icon on screen, in pixels.
+    min_size: f32,
+    /// Largest size of the icon on screen, in pixels.
+    max_size: f32,
+    /// A fixed rotation. The icon faces the camera when unset.
+    rotation: Option<Quat>,
+}
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct PoiMaterial {
+    #[uniform(0)]
+    pub color: LinearRgba,
+    #[texture(1)]
+    #[sampler(2)]
+    pub color_texture: Handle<Image>,
+    /// The distances in metres between which the icon fades out. See
+    /// [`Fade::range`]. Fading is off when `y` isn't positive.
+    #[uniform(3)]
+    pub fade: Vec2,
+}
+
+impl Material for PoiMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "poi.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // Icons with a fixed rotation can be seen from behind.
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+}
+
+/// How a POI is drawn on the compass and world map.
+#[derive(Component)]
+pub struct PoiMapIcon {
+    pub icon: Handle<Image>,
+    /// Size in pixels.
+    pub size: f32,
+    pub scale_with_zoom: bool,
+}
+
+/// Turn the POI icons to face the camera and keep their size on screen
+/// between their `minSize` and `maxSize`.
+fn billboard_system(
+    camera: Query<(&Transform, &Camera, &Projection), With<Camera3d>>,
+    mut icons: Query<(&mut Transform, &GlobalTransform, &PoiIcon), Without<Camera3d>>,
+) {
+    let Ok((camera_transform, camera, projection)) = camera.get_single() else {
+        return;
+    };
+
+    let Projection::Perspective(perspective) = projection else {
+        return;
+    };
+
+    let Some(viewport) = camera.logical_viewport_size() else {
+        return;
+    };
+
+    // Size in pixels of one world unit at a distance of one.
+    let focal_length = viewport.y / (2.0 * (perspective.fov / 2.0).tan());
+
+    for (mut transform, global_transform, icon) in &mut icons {
+        transform.rotation = icon.rotation.unwrap_or(camera_transform.rotation);
+
+        let distance = global_transform
+            .translation()
+            .distance(camera_transform.translation)
+            .max(f32::EPSILON);
+        // The quad is 2 units across.
+        let pixels = 2.0 * icon.size * focal_length / distance;
+        let clamped = pixels.max(icon.min_size).min(icon.max_size);
+        transform.scale = Vec3::splat(icon.size * clamped / pixels);
+    }
+}
+
+/// Icons on the current map that look the same share a material.
+#[derive(Resource, Default)]
+struct PoiMaterials(HashMap<PoiMaterialKey, Handle<PoiMaterial>>);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct PoiMaterialKey {
+    icon: AssetId<Image>,
+    color: [u32; 4],
+    fade: [u32; 2],
+}
+
+impl PoiMaterials {
+    /// The shared material that looks like `material`.
+    fn get(
+        &mut self,
+        materials: &mut Assets<PoiMaterial>,
+        material: PoiMaterial,
+    ) -> Handle<PoiMaterial> {
+        let key = PoiMaterialKey {
+            icon: material.color_texture.id(),
+            color: material.color.to_f32_array().map(f32::to_bits),
+            fade: material.fade.to_array().map(f32::to_bits),
+        };
+        self.0
+            .entry(key)
+            .or_insert_with(|| materials.add(material))
+            .clone()
+    }
+}
+
+/// The default fade distances changed, so icons that use them need
+/// different materials.
+fn refade_system(
+    settings: Res<FadeSettings>,
+    mut cache: ResMut<PoiMaterials>,
+    mut materials: ResMut<Assets<PoiMaterial>>,
+    mut icons: Query<(&Fade, &mut Handle<PoiMaterial>)>,
+) {
+    // Spawned icons hold on to their old materials until they're
+    // replaced, and the rest are dropped.
+    cache.0.clear();
+    for (fade, mut handle) in &mut icons {
+        let Some(material) = materials.get(&*handle) else {
+            continue;
+        };
+        let material = PoiMaterial {
+            fade: fade.uniform(&settings),
+            ..material.clone()
+        };
+        *handle = cache.get(&mut materials, material);
+    }
+}
+
+/// The `color` and `tint` of a POI or trail, with `alpha` applied.
+pub(super) fn display_color(display: &DisplayAttributes) -> Color {
+    let color = LinearRgba::from(display.color.unwrap_or(Color::WHITE));
+    let tint = LinearRgba::from(display.tint.unwrap_or(Color::WHITE));
+    LinearRgba::new(
+        color.red * tint.red,
+        color.green * tint.green,
+        color.blue * tint.blue,
+        color.alpha * tint.alpha * display.alpha.unwrap_or(1.0),
+    )
+    .into()
+}
+
 #[derive(Component)]
-pub struct DisappearNearby;
+pub struct DisappearNearby(pub Behavior);
 
 fn disappear_nearby_system(
     mut commands: Commands,
-    query: Query<(Entity, &Transform), With<DisappearNearby>>,
+    query: Query<(Entity, &DisappearNearby, &super::Marker, &PoiGuid)>,
     player: Query<&Transform, With<Player>>,
+    index: SpatialIndex,
+    mut used_pois: ResMut<UsedPois>,
+    context: Res<PlayerContext>,
 ) {
     if let Ok(player) = player.get_single() {
-        for (entity, transform) in &query {
-            if transform.translation.distance_squared(player.translation) < 10. {
+        let nearby = index
+            .within_radius(player.translation, 10f32.sqrt(), SpatialEntry::is_poi)
+            .into_iter()
+            .filter_map(|nearby| match nearby.entry.kind {
+                SpatialKind::Poi { guid, .. } => Some((&nearby.entry.full_id, guid)),
+                SpatialKind::TrailSegment { .. } => None,
+            })
+            .collect::<HashSet<_>>();
+        if nearby.is_empty() {
+            return;
+        }
+
+        for (entity, disappear, marker, guid) in &query {
+            if nearby.contains(&(&marker.0, *guid)) {
+                used_pois.use_poi(
+                    marker.0.clone(),
+                    *guid,
+                    disappear.0,
+                    &context,
+                    SystemTime::now(),
+                );
                 commands.entity(entity).despawn_recursive();
             }
         }
@@ -44,20 +250,104 @@ fn disappear_nearby_system(
 #[derive(Resource)]
 struct PoiQuad(Handle<Mesh>);
 
+/// Which POIs of the enabled markers are shown: the ones on the current
+/// map whose filters match the player and that haven't been used.
+#[derive(SystemParam)]
+pub(super) struct ShownPois<'w> {
+    map_id: Option<Res<'w, MapId>>,
+    enabled_markers: Res<'w, EnabledMarkers>,
+    used_pois: Res<'w, UsedPois>,
+    context: Res<'w, PlayerContext>,
+    filter_context: Res<'w, FilterContext>,
+}
+
+impl ShownPois<'_> {
+    pub(super) fn map_id(&self) -> Option<u32> {
+        self.map_id.as_ref().map(|map_id| map_id.0)
+    }
+
+    pub(super) fn enabled_markers(&self) -> &EnabledMarkers {
+        &self.enabled_markers
+    }
+
+    pub(super) fn is_shown(&self, full_id: &FullMarkerId, poi: &Poi, now: SystemTime) -> bool {
+        poi.map_id
+            .is_some_and(|map_id| self.map_id() == Some(map_id))
+            && poi.filters.matches(&self.filter_context)
+            && !poi.behavior.is_some_and(|behavior| {
+                self.used_pois
+                    .is_hidden(full_id, poi.guid, behavior, &self.context, now)
+            })
+    }
+}
+
+/// What POI icons are drawn with.
+#[derive(SystemParam)]
+struct PoiAssets<'w> {
+    quad: Res<'w, PoiQuad>,
+    missing_icon: Res<'w, MissingIcon>,
+    fade_settings: Res<'w, FadeSettings>,
+    materials: ResMut<'w, Assets<PoiMaterial>>,
+    cache: ResMut<'w, PoiMaterials>,
+}
+
+impl PoiAssets<'_> {
+    fn material(&mut self, icon: Handle<Image>, color: Color, fade: &Fade) -> Handle<PoiMaterial> {
+        let material = PoiMaterial {
+            color: color.into(),
+            color_texture: icon,
+            fade: fade.uniform(&self.fade_settings),
+        };
+        self.cache.get(&mut self.materials, material)
+    }
+}
+
 fn spawn_pois_system(
     mut commands: Commands,
     mut events: EventReader<MarkerEvent>,
-    assets: Res<PoiQuad>,
+    mut assets: PoiAssets,
     packs: Res<MarkerPacks>,
-    map_id: Res<MapId>,
-    missing_icon: Res<MissingIcon>,
+    mut textures: PackTextures,
+    shown: ShownPois,
+    spawned: Query<(Entity, &super::Marker), With<PoiMarker>>,
 ) {
-    let mut count = 0;
-    for event in events.read() {
-        let MarkerEvent::Enable(full_id) = event else {
-            continue;
-        };
+    let mut full_ids = events
+        .read()
+        .filter_map(|event| match event {
+            MarkerEvent::Enable(full_id) => Some(full_id.clone()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    // The player's profession, mount, etc. changed. Respawn every
+    // enabled marker that has POIs with filters.
+    if shown.filter_context.is_changed() {
+        let filtered = shown
+            .enabled_markers()
+            .iter()
+            .filter(|full_id| {
+                packs.get(&full_id.pack_id).is_some_and(|pack| {
+                    pack.find_by_name(full_id.marker_name.clone())
+                        .and_then(|node_id| pack.get(node_id))
+                        .is_some_and(|node| {
+                            node.data().pois.iter().any(|poi| !poi.filters.is_empty())
+                        })
+                })
+            })
+            .cloned()
+            .collect::<HashSet<_>>();
+
+        for (entity, marker) in &spawned {
+            if filtered.contains(&marker.0) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        full_ids.extend(filtered);
+    }
 
+    let now = SystemTime::now();
+    let mut count = 0;
+    for full_id in &full_ids {
         let Some(pack) = &packs.get(&full_id.pack_id) else {
             continue;
         };
@@ -73,7 +363,7 @@ fn spawn_pois_system(
         for poi in marker
             .pois
             .iter()
-            .filter(|poi| poi.map_id == Some(map_id.0))
+            .filter(|poi| shown.is_shown(full_id, poi, now))
         {
             let Some(pos) = poi.position.map(|position| Vec3 {
                 x: position.x,
@@ -84,58 +374,86 @@ fn spawn_pois_system(
                 continue;
             };
 
-            let icon = poi
-                .icon_file
-                .clone()
-                .or_else(|| marker.icon_file.clone())
-                .map(|icon_path| icon_path.into_string())
-                .and_then(|path| pack.get_image(&path));
+            let display = poi.display;
+            let fade = Fade::new(&display);
+            let height_offset = display.height_offset.unwrap_or(DEFAULT_HEIGHT_OFFSET);
+
+            let icon = poi.icon_file.as_ref().and_then(|path| {
+                textures.get(&full_id.pack_id, poi.from_pack.as_ref(), path.as_str())
+            });
 
-            let mut builder = commands.spawn(TransformBundle::from_transform(
+            let mut builder = commands.spawn(SpatialBundle::from_transform(
                 Transform::from_translation(pos),
             ));
 
-            if let Some(icon) = icon {
-                builder.with_children(|parent| {
-                    parent.spawn(BillboardTextureBundle {
-                        mesh: BillboardMeshHandle(assets.0.clone()),
-                        texture: BillboardTextureHandle(icon),
-                        ..default()
-                    });
-                });
+            let (icon, size) = if let Some(icon) = icon {
+                (icon, display.icon_size.unwrap_or(1.0))
             } else {
                 warn!("No icon for {:?}", full_id);
                 builder.with_children(|parent| {
-                    parent.spawn(BillboardTextureBundle {
-                        mesh: BillboardMeshHandle(assets.0.clone()),
-                        texture: BillboardTextureHandle(missing_icon.0.clone()),
-                        transform: Transform::from_scale(Vec3::splat(0.25)),
-                        ..default()
-                    });
                     let display_name = marker.label.to_string();
-                    parent.spawn(BillboardTextBundle {
-                        text: Text::from_section(
-                            display_name,
-                            TextStyle {
-                                font_size: 32.,
-                                ..default()
-                            },
-                        ),
-                        transform: Transform::from_scale(Vec3::splat(0.01))
-                            .with_translation(Vec3::Y * -0.5),
-                        ..default()
-                    });
+                    parent.spawn((
+                        fade,
+                        BillboardTextBundle {
+                            text: Text::from_section(
+                                display_name,
+                                TextStyle {
+                                    font_size: 32.,
+                                    ..default()
+                                },
+                            ),
+                            transform: Transform::from_scale(Vec3::splat(0.01))
+                                .with_translation(Vec3::Y * (height_offset - 0.5)),
+                            ..default()
+                        },
+                    ));
                 });
-            }
+                (assets.missing_icon.0.clone(), 0.25)
+            };
+
+            let material = assets.material(icon.clone(), display_color(&display), &fade);
 
-            if let Some(Behavior::ReappearDaily) = marker.behavior {
-                builder.insert(DisappearNearby);
-            } else if let Some(Behavior::DisappearOnUse) = marker.behavior {
-                builder.insert(DisappearNearby);
+            builder.with_children(|parent| {
+                parent.spawn((
+                    PoiIcon {
+                        size,
+                        min_size: display.min_size.unwrap_or(DEFAULT_MIN_SIZE),
+                        max_size: display.max_size.unwrap_or(DEFAULT_MAX_SIZE),
+                        rotation: display.rotate.map(|rotate| {
+                            Quat::from_euler(
+                                EulerRot::XYZ,
+                                rotate.x.to_radians(),
+                                rotate.y.to_radians(),
+                                rotate.z.to_radians(),
+                            )
+                        }),
+                    },
+                    fade,
+                    MaterialMeshBundle {
+                        mesh: assets.quad.0.clone(),
+                        material,
+                        transform: Transform::from_translation(Vec3::Y * height_offset),
+                        ..default()
+                    },
+                ));
+            });
+
+            builder.insert(PoiMapIcon {
+                icon,
+                size: display.map_display_size.unwrap_or(DEFAULT_MAP_DISPLAY_SIZE),
+                scale_with_zoom: display.scale_on_map_with_zoom.unwrap_or(true),
+            });
+
+            match poi.behavior {
+                None | Some(Behavior::AlwaysVisible) => {}
+                Some(behavior) => {
+                    builder.insert(DisappearNearby(behavior));
+                }
             }
 
             builder.insert(PoiMarker);
             builder.insert(super::Marker(full_id.clone()));
+            builder.insert(poi.guid);
 
             count += 1;
         }
@@ -169,10 +487,16 @@ fn despawn_pois_system(
     }
 }
 
-fn map_exit_system(mut commands: Commands, poi_query: Query<Entity, With<PoiMarker>>) {
+fn map_exit_system(
+    mut commands: Commands,
+    mut cache: ResMut<PoiMaterials>,
+    poi_query: Query<Entity, With<PoiMarker>>,
+) {
     for entity in &poi_query {
         commands.entity(entity).despawn_recursive();
     }
+    // Let go of the icons of this map.
+    cache.0.clear();
 }
 
 fn map_enter_system(enabled_markers: Res<EnabledMarkers>, mut events: EventWriter<MarkerEvent>) {
@@ -188,8 +512,15 @@ pub(super) struct Plugin;
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(BillboardPlugin);
+        app.add_plugins(MaterialPlugin::<PoiMaterial>::default());
+        app.init_resource::<PoiMaterials>();
 
         app.add_systems(Startup, setup);
+        app.add_systems(Update, billboard_system.run_if(in_state(GameState::InGame)));
+        app.add_systems(
+            Update,
+            refade_system.run_if(resource_changed::<FadeSettings>),
+        );
         app.add_systems(
             Update,
             disappear_nearby_system
@@ -199,7 +530,13 @@ impl bevy::prelude::Plugin for Plugin {
 
         app.add_systems(
             Update,
-            (spawn_pois_system, despawn_pois_system)
+            spawn_pois_system
+                .run_if(in_state(GameState::InGame))
+                .run_if(on_event::<MarkerEvent>().or_else(resource_changed::<FilterContext>)),
+        );
+        app.add_systems(
+            Update,
+            despawn_pois_system
                 .run_if(in_state(GameState::InGame))
                 .run_if(on_event::<MarkerEvent>()),
         );
@@ -0,0 +1,567 @@
+//! A uniform grid over the shown POIs and the trails of the enabled
+//! markers on the current map, so finding what's near a point doesn't
+//! have to look at every marker.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy::utils::HashSet;
+use std::time::SystemTime;
+
+use super::behavior::UsedPois;
+use super::poi::ShownPois;
+use super::trail::TrailSettings;
+use super::EnabledMarkers;
+use super::MapMarkers;
+use crate::parser::filter::FilterContext;
+use crate::parser::pack::FullMarkerId;
+use crate::parser::pack::PoiGuid;
+use crate::parser::trail::join_corrupt_segments;
+use crate::parser::MarkerPacks;
+
+/// Width of a grid cell in metres. Cells are columns: height isn't
+/// indexed.
+const CELL_SIZE: f32 = 32.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpatialKind {
+    Poi {
+        guid: PoiGuid,
+        position: Vec3,
+    },
+    /// A straight piece of one of the marker's trails. `index` is the
+    /// index into the marker's trails.
+    TrailSegment {
+        index: usize,
+        start: Vec3,
+        end: Vec3,
+    },
+}
+
+/// A POI or piece of trail in world coordinates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpatialEntry {
+    pub full_id: FullMarkerId,
+    pub kind: SpatialKind,
+}
+
+impl SpatialEntry {
+    pub fn is_poi(&self) -> bool {
+        matches!(self.kind, SpatialKind::Poi { .. })
+    }
+
+    /// The point of the entry closest to `point`.
+    pub fn closest_point(&self, point: Vec3) -> Vec3 {
+        match self.kind {
+            SpatialKind::Poi { position, .. } => position,
+            SpatialKind::TrailSegment { start, end, .. } => {
+                let line = end - start;
+                let t = (point - start).dot(line) / line.length_squared().max(f32::EPSILON);
+                start + line * t.clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+/// An entry found near a point.
+#[derive(Clone, Copy, Debug)]
+pub struct Nearby<'a> {
+    pub entry: &'a SpatialEntry,
+    /// The point of the entry closest to where was searched.
+    pub point: Vec3,
+    pub distance: f32,
+}
+
+impl<'a> Nearby<'a> {
+    fn new(entry: &'a SpatialEntry, point: Vec3) -> Self {
+        let closest = entry.closest_point(point);
+        Self {
+            entry,
+            point: closest,
+            distance: closest.distance(point),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct SpatialGrid {
+    entries: Vec<SpatialEntry>,
+    /// Indices into `entries` by cell. Trail segments are in every cell
+    /// they cross.
+    cells: HashMap<IVec2, Vec<usize>>,
+    /// The smallest and largest cell with anything in it.
+    bounds: Option<(IVec2, IVec2)>,
+}
+
+impl SpatialGrid {
+    fn cell(point: Vec3) -> IVec2 {
+        IVec2::new(
+            (point.x / CELL_SIZE).floor() as i32,
+            (point.z / CELL_SIZE).floor() as i32,
+        )
+    }
+
+    fn insert(&mut self, entry: SpatialEntry) {
+        let mut cells = HashSet::new();
+        match entry.kind {
+            SpatialKind::Poi { position, .. } => {
+                cells.insert(Self::cell(position));
+            }
+            SpatialKind::TrailSegment { start, end, .. } => {
+                // A piece no longer than a cell can only cross the cells
+                // in the box around it.
+                let pieces = (start.distance(end) / CELL_SIZE).ceil().max(1.0) as usize;
+                for piece in 0..pieces {
+                    let a = start.lerp(end, piece as f32 / pieces as f32);
+                    let b = start.lerp(end, (piece + 1) as f32 / pieces as f32);
+                    let min = Self::cell(a.min(b));
+                    let max = Self::cell(a.max(b));
+                    for x in min.x..=max.x {
+                        for y in min.y..=max.y {
+                            cells.insert(IVec2::new(x, y));
+                        }
+                    }
+                }
+            }
+        }
+
+        let index = self.entries.len();
+        self.entries.push(entry);
+        for cell in cells {
+            self.cells.entry(cell).or_default().push(index);
+            self.bounds = Some(match self.bounds {
+                Some((min, max)) => (min.min(cell), max.max(cell)),
+                None => (cell, cell),
+            });
+        }
+    }
+
+    /// Add every entry in `cell` that hasn't been `seen` and passes
+    /// `filter` to `found`.
+    fn search_cell<'a>(
+        &'a self,
+        cell: IVec2,
+        point: Vec3,
+        filter: &impl Fn(&SpatialEntry) -> bool,
+        seen: &mut HashSet<usize>,
+        found: &mut Vec<Nearby<'a>>,
+    ) {
+        for &index in self.cells.get(&cell).into_iter().flatten() {
+            let entry = &self.entries[index];
+            if seen.insert(index) && filter(entry) {
+                found.push(Nearby::new(entry, point));
+            }
+        }
+    }
+
+    fn within_radius(
+        &self,
+        point: Vec3,
+        radius: f32,
+        filter: impl Fn(&SpatialEntry) -> bool,
+    ) -> Vec<Nearby<'_>> {
+        let Some((lo, hi)) = self.bounds else {
+            return vec![];
+        };
+        let min = Self::cell(point - Vec3::splat(radius)).max(lo);
+        let max = Self::cell(point + Vec3::splat(radius)).min(hi);
+
+        let mut seen = HashSet::new();
+        let mut found = vec![];
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                self.search_cell(IVec2::new(x, y), point, &filter, &mut seen, &mut found);
+            }
+        }
+        found.retain(|nearby| nearby.distance <= radius);
+        found.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        found
+    }
+
+    /// Search rings of cells around `point` until nothing further out
+    /// could be closer than what's been found.
+    fn nearest_n(
+        &self,
+        point: Vec3,
+        n: usize,
+        filter: impl Fn(&SpatialEntry) -> bool,
+    ) -> Vec<Nearby<'_>> {
+        let Some((lo, hi)) = self.bounds else {
+            return vec![];
+        };
+        if n == 0 {
+            return vec![];
+        }
+
+        let center = Self::cell(point);
+        // Rings before the first and after the last have no cells with
+        // anything in them.
+        let first_ring = (lo - center)
+            .max(center - hi)
+            .max(IVec2::ZERO)
+            .max_element();
+        let last_ring = (center - lo).abs().max((hi - center).abs()).max_element();
+
+        let mut seen = HashSet::new();
+        let mut found = vec![];
+        for ring in first_ring..=last_ring {
+            for x in (center.x - ring).max(lo.x)..=(center.x + ring).min(hi.x) {
+                if (x - center.x).abs() == ring {
+                    for y in (center.y - ring).max(lo.y)..=(center.y + ring).min(hi.y) {
+                        self.search_cell(IVec2::new(x, y), point, &filter, &mut seen, &mut found);
+                    }
+                } else {
+                    for y in [center.y - ring, center.y + ring] {
+                        if (lo.y..=hi.y).contains(&y) {
+                            self.search_cell(
+                                IVec2::new(x, y),
+                                point,
+                                &filter,
+                                &mut seen,
+                                &mut found,
+                            );
+                        }
+                    }
+                }
+            }
+
+            found.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+            found.truncate(n);
+            // Every cell in the rings still to search is at least this
+            // far away.
+            let searched = ring as f32 * CELL_SIZE;
+            if found.len() == n && found[n - 1].distance <= searched {
+                break;
+            }
+        }
+        found
+    }
+}
+
+/// Find the POIs and trails of the enabled markers on the current map
+/// near a point.
+#[derive(SystemParam)]
+pub struct SpatialIndex<'w> {
+    grid: Res<'w, SpatialGrid>,
+}
+
+impl SpatialIndex<'_> {
+    /// The `n` entries closest to `point` that pass `filter`, closest
+    /// first.
+    pub fn nearest_n(
+        &self,
+        point: Vec3,
+        n: usize,
+        filter: impl Fn(&SpatialEntry) -> bool,
+    ) -> Vec<Nearby<'_>> {
+        self.grid.nearest_n(point, n, filter)
+    }
+
+    /// Every entry within `radius` of `point` that passes `filter`,
+    /// closest first.
+    pub fn within_radius(
+        &self,
+        point: Vec3,
+        radius: f32,
+        filter: impl Fn(&SpatialEntry) -> bool,
+    ) -> Vec<Nearby<'_>> {
+        self.grid.within_radius(point, radius, filter)
+    }
+}
+
+/// Positions in packs have their Z axis flipped compared to the world.
+fn to_world(position: Vec3) -> Vec3 {
+    Vec3::new(position.x, position.y, -position.z)
+}
+
+/// Index the shown POIs and the trails of the enabled markers on the
+/// current map.
+fn rebuild_system(
+    mut grid: ResMut<SpatialGrid>,
+    packs: Res<MarkerPacks>,
+    shown: ShownPois,
+    map_markers: Res<MapMarkers>,
+    settings: Res<TrailSettings>,
+) {
+    *grid = SpatialGrid::default();
+    let Some(map_id) = shown.map_id() else {
+        return;
+    };
+
+    let now = SystemTime::now();
+    for full_id in shown.enabled_markers().intersection(&map_markers) {
+        let Some(pack) = packs.get(&full_id.pack_id) else {
+            continue;
+        };
+
+        let Some(marker) = pack
+            .find_by_name(full_id.marker_name.clone())
+            .and_then(|node_id| pack.get(node_id))
+            .map(|node| node.data())
+        else {
+            continue;
+        };
+
+        for poi in marker
+            .pois
+            .iter()
+            .filter(|poi| shown.is_shown(full_id, poi, now))
+        {
+            let Some(position) = poi.position else {
+                continue;
+            };
+            grid.insert(SpatialEntry {
+                full_id: full_id.clone(),
+                kind: SpatialKind::Poi {
+                    guid: poi.guid,
+                    position: to_world(position),
+                },
+            });
+        }
+
+        for (index, trail) in marker
+            .trails
+            .iter()
+            .enumerate()
+            .filter(|(_, trail)| trail.map_id == map_id)
+        {
+            let pack_id = trail.from_pack.as_ref().unwrap_or(&full_id.pack_id);
+            let segments = if settings.repair_packs.contains(pack_id) {
+                join_corrupt_segments(trail.segments.clone())
+            } else {
+                trail.segments.clone()
+            };
+            for segment in segments {
+                for pair in segment.windows(2) {
+                    grid.insert(SpatialEntry {
+                        full_id: full_id.clone(),
+                        kind: SpatialKind::TrailSegment {
+                            index,
+                            start: to_world(pair[0]),
+                            end: to_world(pair[1]),
+                        },
+                    });
+                }
+            }
+        }
+    }
+
+    debug!("Indexed {} POIs and trail segments.", grid.entries.len());
+}
+
+pub(super) struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialGrid>();
+        app.add_systems(
+            Update,
+            rebuild_system
+                .run_if(resource_exists::<MarkerPacks>)
+                .run_if(
+                    resource_changed::<MapMarkers>
+                        .or_else(resource_changed::<EnabledMarkers>)
+                        .or_else(resource_changed::<MarkerPacks>)
+                        .or_else(resource_changed::<TrailSettings>)
+                        .or_else(resource_changed::<UsedPois>)
+                        .or_else(resource_changed::<FilterContext>),
+                ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bevy::ecs::system::RunSystemOnce as _;
+    use orrient_core::prelude::*;
+    use tempfile::tempdir;
+
+    use crate::marker::behavior::PlayerContext;
+    use crate::parser::filter::Race;
+    use crate::parser::pack::MarkerName;
+    use crate::parser::read_marker_pack;
+    use crate::parser::trail::to_file;
+    use crate::parser::trail::TrailData;
+    use crate::parser::PackId;
+
+    fn full_id(name: &str) -> FullMarkerId {
+        PackId("test".into()).with_marker(MarkerName(vec![name.into()]))
+    }
+
+    fn poi(name: &str, position: Vec3) -> SpatialEntry {
+        SpatialEntry {
+            full_id: full_id(name),
+            kind: SpatialKind::Poi {
+                guid: PoiGuid([0; 16]),
+                position,
+            },
+        }
+    }
+
+    fn segment(name: &str, start: Vec3, end: Vec3) -> SpatialEntry {
+        SpatialEntry {
+            full_id: full_id(name),
+            kind: SpatialKind::TrailSegment {
+                index: 0,
+                start,
+                end,
+            },
+        }
+    }
+
+    fn names(found: &[Nearby]) -> Vec<String> {
+        found
+            .iter()
+            .map(|nearby| nearby.entry.full_id.marker_name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut grid = SpatialGrid::default();
+        assert!(grid.nearest_n(Vec3::ZERO, 1, |_| true).is_empty());
+
+        grid.insert(poi("a", Vec3::new(1.0, 0.0, 0.0)));
+        grid.insert(poi("b", Vec3::new(-50.0, 0.0, 0.0)));
+        grid.insert(poi("c", Vec3::new(0.0, 100.0, 5.0)));
+        grid.insert(poi("d", Vec3::new(500.0, 0.0, -500.0)));
+        // Crosses a lot of cells without anything near its ends.
+        grid.insert(segment(
+            "e",
+            Vec3::new(-1000.0, 0.0, 20.0),
+            Vec3::new(1000.0, 0.0, 20.0),
+        ));
+
+        let found = grid.nearest_n(Vec3::ZERO, 3, |_| true);
+        assert_eq!(names(&found), ["a", "e", "b"]);
+        assert_eq!(found[1].point, Vec3::new(0.0, 0.0, 20.0));
+        assert_eq!(found[1].distance, 20.0);
+
+        let found = grid.nearest_n(Vec3::ZERO, 10, SpatialEntry::is_poi);
+        assert_eq!(names(&found), ["a", "b", "c", "d"]);
+
+        // Far outside the grid.
+        let found = grid.nearest_n(Vec3::new(1e6, 0.0, -1e6), 1, |_| true);
+        assert_eq!(names(&found), ["d"]);
+
+        let found = grid.within_radius(Vec3::ZERO, 50.0, |_| true);
+        assert_eq!(names(&found), ["a", "e", "b"]);
+        let found = grid.within_radius(Vec3::new(900.0, 0.0, 0.0), 30.0, |_| true);
+        assert_eq!(names(&found), ["e"]);
+        assert!(grid
+            .within_radius(Vec3::ZERO, 10.0, |entry| !entry.is_poi())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_matches_linear_search() {
+        let mut grid = SpatialGrid::default();
+        // Deterministic points scattered over a few hundred metres.
+        let mut seed: u32 = 1;
+        let mut random = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 400.0 - 200.0
+        };
+        for i in 0..200 {
+            let start = Vec3::new(random(), random() / 10.0, random());
+            if i % 2 == 0 {
+                grid.insert(poi(&i.to_string(), start));
+            } else {
+                let end = start + Vec3::new(random(), 0.0, random()) / 4.0;
+                grid.insert(segment(&i.to_string(), start, end));
+            }
+        }
+
+        for _ in 0..20 {
+            let point = Vec3::new(random(), 0.0, random());
+            let mut expected = grid
+                .entries
+                .iter()
+                .map(|entry| Nearby::new(entry, point).distance)
+                .collect::<Vec<_>>();
+            expected.sort_by(f32::total_cmp);
+
+            let found = grid.nearest_n(point, 5, |_| true);
+            let distances = found
+                .iter()
+                .map(|nearby| nearby.distance)
+                .collect::<Vec<_>>();
+            assert_eq!(distances, expected[..5]);
+
+            let found = grid.within_radius(point, 40.0, |_| true);
+            let within = expected
+                .iter()
+                .take_while(|distance| **distance <= 40.0)
+                .count();
+            assert_eq!(found.len(), within);
+        }
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let dir = tempdir().unwrap();
+        let pack_dir = dir.path().join("test");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        to_file(
+            pack_dir.join("a.trl"),
+            &TrailData {
+                version: 0,
+                map_id: 15,
+                segments: vec![vec![Vec3::new(0.0, 0.0, 10.0), Vec3::new(10.0, 0.0, 10.0)]],
+            },
+        )
+        .unwrap();
+        std::fs::write(
+            pack_dir.join("test.xml"),
+            r#"
+<OverlayData>
+  <MarkerCategory name="A" texture="trail.png" />
+  <MarkerCategory name="B" />
+  <POIs>
+    <POI type="A" MapID="15" xpos="1" ypos="2" zpos="3" />
+    <POI type="A" MapID="15" xpos="0" ypos="0" zpos="0" race="norn" />
+    <POI type="A" MapID="50" xpos="0" ypos="0" zpos="0" />
+    <POI type="B" MapID="15" xpos="0" ypos="0" zpos="0" />
+    <Trail type="A" trailData="a.trl" />
+  </POIs>
+</OverlayData>
+"#,
+        )
+        .unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(MarkerPacks::new(HashMap::from_iter([(
+            PackId("test".into()),
+            read_marker_pack(&pack_dir).unwrap(),
+        )])));
+        world.insert_resource(MapId(15));
+        world.insert_resource(MapMarkers(HashSet::from_iter([full_id("A"), full_id("B")])));
+        world.insert_resource(EnabledMarkers(HashSet::from_iter([full_id("A")])));
+        world.init_resource::<TrailSettings>();
+        world.init_resource::<UsedPois>();
+        world.init_resource::<PlayerContext>();
+        // The POI only for norn is left out.
+        world.insert_resource(FilterContext {
+            race: Some(Race::Asura),
+            ..default()
+        });
+        world.init_resource::<SpatialGrid>();
+        world.run_system_once(rebuild_system);
+
+        let index = world.run_system_once(|index: SpatialIndex| {
+            index
+                .nearest_n(Vec3::ZERO, 10, |_| true)
+                .into_iter()
+                .map(|nearby| (nearby.entry.clone(), nearby.point))
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(index.len(), 2);
+        assert!(index.iter().all(|(entry, _)| entry.full_id == full_id("A")));
+        assert!(matches!(
+            index[0].0.kind,
+            SpatialKind::Poi { position, .. } if position == Vec3::new(1.0, 2.0, -3.0)
+        ));
+        assert_eq!(index[1].1, Vec3::new(0.0, 0.0, -10.0));
+    }
+}
@@ -1,5 +1,6 @@
 use orrient_core::prelude::*;
 
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use bevy::render::mesh::Indices;
 use bevy::render::mesh::PrimitiveTopology;
@@ -7,67 +8,260 @@ use bevy::render::render_asset::RenderAssetUsages;
 use bevy::render::render_resource::AsBindGroup;
 use bevy::render::render_resource::ShaderRef;
 
-use itertools::Itertools;
+use bevy::utils::HashMap;
+use bevy::utils::HashSet;
 
+use anyhow::anyhow;
+use anyhow::Result;
+use std::fs::File;
+use std::path::PathBuf;
+
+use super::output_error;
+use super::poi::display_color;
+use super::state_dir;
+use super::visibility::Fade;
+use super::visibility::FadeSettings;
 use crate::events::MarkerEvent;
+use crate::parser::pack::FullMarkerId;
+use crate::parser::textures::PackTextures;
+use crate::parser::trail::join_corrupt_segments;
 use crate::parser::MarkerPacks;
+use crate::parser::PackId;
 
 #[derive(Component)]
 pub struct TrailMesh;
 
+/// Half the width of a trail with a `trailScale` of 1.
 const TRAIL_WIDTH: f32 = 0.5;
 
-#[derive(Clone, Copy)]
-struct OrientedPoint {
-    position: Vec3,
-    forward: Vec3,
-    distance: f32,
+#[derive(Resource, Clone, Default, Debug)]
+pub struct TrailSettings {
+    /// Packs whose trail files have random `Vec3::ZERO` points in them.
+    /// Segments in these packs are joined back together unless the
+    /// break between them is long. Read from `repair_trails.ron`.
+    pub repair_packs: HashSet<PackId>,
+    /// How trail meshes are built. `width` and `is_wall` are overridden
+    /// by each trail's attributes.
+    pub mesh: TrailMeshOptions,
+}
+
+/// How the corners of a trail are joined.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrailJoin {
+    /// Extend both edges until they meet. Corners where the miter would
+    /// be longer than `limit` times the width are bevelled instead.
+    Miter { limit: f32 },
+    /// Cut every corner off.
+    Bevel,
 }
 
-pub fn create_trail_mesh(path: impl Iterator<Item = Vec3>) -> Mesh {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrailMeshOptions {
+    /// Half the width of the trail.
+    pub width: f32,
+    pub is_wall: bool,
+    /// Points added between each pair of points along a Catmull-Rom
+    /// spline. `0` keeps the path as it is.
+    pub subdivisions: u32,
+    pub join: TrailJoin,
+    /// How many times the texture repeats along a metre of trail.
+    pub uv_per_metre: f32,
+}
+
+impl Default for TrailMeshOptions {
+    fn default() -> Self {
+        Self {
+            width: TRAIL_WIDTH,
+            is_wall: false,
+            subdivisions: 0,
+            join: TrailJoin::Miter { limit: MITER_LIMIT },
+            uv_per_metre: 1.0,
+        }
+    }
+}
+
+const MITER_LIMIT: f32 = 4.0;
+
+/// Corners that turn less than this (as the cosine between the sides)
+/// aren't worth bevelling.
+const BEVEL_MIN_COS: f32 = 0.999;
+
+/// Add `subdivisions` points between each pair of points, following a
+/// Catmull-Rom spline through them. The ends are clamped so the spline
+/// still starts and ends on the path.
+fn subdivide(points: &[Vec3], subdivisions: u32) -> Vec<Vec3> {
+    if subdivisions == 0 || points.len() < 2 {
+        return points.to_vec();
+    }
+
+    let last = points.len() - 1;
+    let mut result = Vec::with_capacity(last * (subdivisions as usize + 1) + 1);
+    for i in 0..last {
+        let p0 = points[i.saturating_sub(1)];
+        let p1 = points[i];
+        let p2 = points[i + 1];
+        let p3 = points[(i + 2).min(last)];
+        result.push(p1);
+        for step in 1..=subdivisions {
+            let t = step as f32 / (subdivisions + 1) as f32;
+            let t2 = t * t;
+            let t3 = t2 * t;
+            result.push(
+                0.5 * (2.0 * p1
+                    + (p2 - p0) * t
+                    + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                    + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3),
+            );
+        }
+    }
+    result.push(points[last]);
+    result
+}
+
+/// The up vector of every point on the trail: world up made
+/// perpendicular to the trail. Where the trail runs (almost) straight up
+/// or down, the up vector is taken from the nearest point that doesn't,
+/// so the ribbon doesn't twist around on climbs.
+fn up_vectors(tangents: &[Vec3]) -> Vec<Vec3> {
+    let reject = |up: Vec3, tangent: Vec3| up - tangent * up.dot(tangent);
+    let ups = tangents
+        .iter()
+        .map(|tangent| Some(reject(Vec3::Y, *tangent)).filter(|up| up.length_squared() > 0.01))
+        .collect::<Vec<_>>();
+
+    let mut nearest = ups.iter().flatten().next().copied();
+    ups.into_iter()
+        .zip(tangents)
+        .map(|(up, tangent)| {
+            if up.is_some() {
+                nearest = up;
+            }
+            nearest
+                .and_then(|up| reject(up, *tangent).try_normalize())
+                .unwrap_or_else(|| tangent.any_orthonormal_vector())
+        })
+        .collect()
+}
+
+/// Build a single mesh for every segment of a trail. Segments aren't
+/// connected to each other. The trail extends `width` to either side
+/// of its path, or above and below it when it's a wall.
+pub fn create_trail_mesh(
+    segments: impl IntoIterator<Item = impl IntoIterator<Item = Vec3>>,
+    options: &TrailMeshOptions,
+) -> Mesh {
+    let width = options.width;
     let mut indices: Vec<u32> = vec![];
     let mut positions: Vec<Vec3> = vec![];
     let mut uvs: Vec<Vec2> = vec![];
     let mut normals: Vec<Vec3> = vec![];
 
-    let mut distance: f32 = 0.0;
-    let points = path.tuple_windows().map(|(prev_pos, next_pos)| {
-        let forward = *Dir3::new_unchecked((next_pos - prev_pos).normalize());
-        distance += prev_pos.distance(next_pos);
-        OrientedPoint {
-            position: next_pos,
-            forward,
-            distance,
+    for path in segments {
+        let mut points = path.into_iter().collect::<Vec<_>>();
+        points.dedup_by(|a, b| a.distance_squared(*b) < 1e-6);
+        if points.len() < 2 {
+            continue;
         }
-    });
+        let points = subdivide(&points, options.subdivisions);
 
-    for (prev_pos, next_pos) in points.tuple_windows() {
-        let prev_left_vertex = positions.len() as u32;
-        positions.push(prev_pos.position + prev_pos.forward.cross(Vec3::NEG_Y) * TRAIL_WIDTH);
-        uvs.push(Vec2::new(0.0, prev_pos.distance));
-        normals.push(Vec3::Z);
+        // The direction into and out of each point. The ends only have
+        // the one.
+        let directions = points
+            .iter()
+            .enumerate()
+            .map(|(i, position)| {
+                let incoming = (i > 0).then(|| (*position - points[i - 1]).normalize());
+                let outgoing = points
+                    .get(i + 1)
+                    .map(|next| (*next - *position).normalize());
+                let incoming = incoming.or(outgoing).unwrap();
+                (incoming, outgoing.unwrap_or(incoming))
+            })
+            .collect::<Vec<_>>();
+        let tangents = directions
+            .iter()
+            .map(|(incoming, outgoing)| {
+                (*incoming + *outgoing).try_normalize().unwrap_or(*incoming)
+            })
+            .collect::<Vec<_>>();
+        let ups = up_vectors(&tangents);
+
+        let mut distance: f32 = 0.0;
+        // The first vertex of the last left and right pair pushed.
+        let mut previous_pair: Option<u32> = None;
+        for (i, &position) in points.iter().enumerate() {
+            if i > 0 {
+                distance += position.distance(points[i - 1]);
+            }
 
-        let prev_right_vertex = positions.len() as u32;
-        positions.push(prev_pos.position + prev_pos.forward.cross(Vec3::Y) * TRAIL_WIDTH);
-        uvs.push(Vec2::new(1.0, prev_pos.distance));
-        normals.push(Vec3::Z);
+            let (incoming, outgoing) = directions[i];
+            let up = ups[i];
+            let side = tangents[i].cross(up).normalize();
 
-        let next_left_vertex = positions.len() as u32;
-        positions.push(next_pos.position + next_pos.forward.cross(Vec3::NEG_Y) * TRAIL_WIDTH);
-        uvs.push(Vec2::new(0.0, next_pos.distance));
-        normals.push(Vec3::Z);
+            // Each pair is the (left, right) offset from `position`.
+            let mut pairs: Vec<(Vec3, Vec3)> = vec![];
+            let normal;
+            if options.is_wall {
+                normal = side;
+                pairs.push((-Vec3::Y * width, Vec3::Y * width));
+            } else {
+                normal = up;
+                let side_in = incoming.cross(up).normalize();
+                let side_out = outgoing.cross(up).normalize();
+                // How much longer the miter is than the width.
+                let miter = (side_in + side_out).normalize_or_zero();
+                let scale = 1.0 / miter.dot(side_in).max(f32::EPSILON);
+                let bevel = match options.join {
+                    TrailJoin::Miter { limit } => scale > limit,
+                    TrailJoin::Bevel => side_in.dot(side_out) < BEVEL_MIN_COS,
+                };
 
-        let next_right_vertex = positions.len() as u32;
-        positions.push(next_pos.position + next_pos.forward.cross(Vec3::Y) * TRAIL_WIDTH);
-        uvs.push(Vec2::new(1.0, next_pos.distance));
-        normals.push(Vec3::Z);
+                if bevel {
+                    // The inside of the corner is still mitred, but
+                    // hairpins would send it off into the distance.
+                    let limit = match options.join {
+                        TrailJoin::Miter { limit } => limit,
+                        TrailJoin::Bevel => MITER_LIMIT,
+                    };
+                    let inner = miter * width * scale.min(limit.max(1.0));
+                    // Turning left puts the outside of the corner on the right.
+                    if incoming.cross(outgoing).dot(up) > 0.0 {
+                        pairs.push((-inner, side_in * width));
+                        pairs.push((-inner, side_out * width));
+                    } else {
+                        pairs.push((-side_in * width, inner));
+                        pairs.push((-side_out * width, inner));
+                    }
+                } else {
+                    let offset = miter * width * scale;
+                    pairs.push((-offset, offset));
+                }
+            }
 
-        indices.push(prev_left_vertex);
-        indices.push(prev_right_vertex);
-        indices.push(next_right_vertex);
-        indices.push(next_right_vertex);
-        indices.push(next_left_vertex);
-        indices.push(prev_left_vertex);
+            let v = distance * options.uv_per_metre;
+            for (left, right) in pairs {
+                let left_vertex = positions.len() as u32;
+                positions.push(position + left);
+                uvs.push(Vec2::new(0.0, v));
+                normals.push(normal);
+                positions.push(position + right);
+                uvs.push(Vec2::new(1.0, v));
+                normals.push(normal);
+
+                if let Some(prev_left_vertex) = previous_pair {
+                    let prev_right_vertex = prev_left_vertex + 1;
+                    let next_left_vertex = left_vertex;
+                    let next_right_vertex = left_vertex + 1;
+                    indices.push(prev_left_vertex);
+                    indices.push(prev_right_vertex);
+                    indices.push(next_right_vertex);
+                    indices.push(next_right_vertex);
+                    indices.push(next_left_vertex);
+                    indices.push(prev_left_vertex);
+                }
+                previous_pair = Some(left_vertex);
+            }
+        }
     }
 
     Mesh::new(
@@ -90,6 +284,10 @@ pub struct TrailMaterial {
     pub alpha_mode: AlphaMode,
     #[uniform(3)]
     pub speed: f32,
+    /// The distances in metres between which the trail fades out. See
+    /// [`Fade::range`]. Fading is off when `y` isn't positive.
+    #[uniform(4)]
+    pub fade: Vec2,
 }
 
 impl Material for TrailMaterial {
@@ -102,17 +300,148 @@ impl Material for TrailMaterial {
     }
 }
 
-fn show_trails(
+/// A spawned trail: one route of a marker.
+#[derive(Component, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TrailId {
+    pub full_id: FullMarkerId,
+    /// Index into the marker's trails.
+    pub index: usize,
+}
+
+/// Meshes and materials of trails that have been spawned on the current
+/// map, so toggling a category doesn't rebuild them. The handles keep
+/// the assets and their textures loaded until the cache is cleared.
+#[derive(Resource, Default)]
+pub struct TrailMeshCache {
+    meshes: HashMap<TrailId, Handle<Mesh>>,
+    /// Trails that look the same share a material.
+    materials: HashMap<MaterialKey, Handle<TrailMaterial>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct MaterialKey {
+    texture: Option<AssetId<Image>>,
+    color: [u32; 4],
+    speed: u32,
+    fade: [u32; 2],
+}
+
+impl MaterialKey {
+    fn new(material: &TrailMaterial) -> Self {
+        Self {
+            texture: material.color_texture.as_ref().map(Handle::id),
+            color: material.color.to_f32_array().map(f32::to_bits),
+            speed: material.speed.to_bits(),
+            fade: material.fade.to_array().map(f32::to_bits),
+        }
+    }
+}
+
+impl TrailMeshCache {
+    /// The shared material that looks like `material`.
+    fn material(
+        &mut self,
+        materials: &mut Assets<TrailMaterial>,
+        material: TrailMaterial,
+    ) -> Handle<TrailMaterial> {
+        self.materials
+            .entry(MaterialKey::new(&material))
+            .or_insert_with(|| materials.add(material))
+            .clone()
+    }
+
+    fn clear(&mut self) {
+        self.meshes.clear();
+        self.materials.clear();
+    }
+}
+
+/// The default fade distances changed, so trails that use them need
+/// different materials.
+fn refade_system(
+    fade_settings: Res<FadeSettings>,
+    mut cache: ResMut<TrailMeshCache>,
+    mut trail_materials: ResMut<Assets<TrailMaterial>>,
+    mut q_trails: Query<(&Fade, &mut Handle<TrailMaterial>), With<TrailMesh>>,
+) {
+    // Spawned trails hold on to their old materials until they're
+    // replaced, and the rest are dropped.
+    cache.materials.clear();
+    for (fade, mut handle) in &mut q_trails {
+        let Some(material) = trail_materials.get(&*handle) else {
+            continue;
+        };
+        let material = TrailMaterial {
+            fade: fade.uniform(&fade_settings),
+            ..material.clone()
+        };
+        *handle = cache.material(&mut trail_materials, material);
+    }
+}
+
+/// Reloaded packs may have changed their trails, and the repaired packs
+/// change how meshes are built.
+fn clear_cache_system(mut cache: ResMut<TrailMeshCache>) {
+    cache.clear();
+}
+
+/// What trails are built and drawn with.
+#[derive(SystemParam)]
+struct TrailAssets<'w> {
+    meshes: ResMut<'w, Assets<Mesh>>,
+    trail_materials: ResMut<'w, Assets<TrailMaterial>>,
+    cache: ResMut<'w, TrailMeshCache>,
+    settings: Res<'w, TrailSettings>,
+    fade_settings: Res<'w, FadeSettings>,
+}
+
+/// Spawn and despawn the trails of the markers named in
+/// [`MarkerEvent`]s, leaving every other trail alone. Events are handled
+/// in order, so enabling and disabling in the same frame works out.
+fn update_trails_system(
     mut commands: Commands,
     mut events: EventReader<MarkerEvent>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut trail_materials: ResMut<Assets<TrailMaterial>>,
+    assets: TrailAssets,
     packs: Res<MarkerPacks>,
+    mut textures: PackTextures,
     map_id: Res<MapId>,
+    q_trails: Query<(Entity, &TrailId), With<TrailMesh>>,
 ) {
+    let TrailAssets {
+        mut meshes,
+        mut trail_materials,
+        mut cache,
+        settings,
+        fade_settings,
+    } = assets;
+    let mut spawned = q_trails
+        .iter()
+        .map(|(entity, trail_id)| (trail_id.clone(), entity))
+        .collect::<HashMap<_, _>>();
+    let mut loaded = 0;
+    let mut unloaded = 0;
+
     for event in events.read() {
-        let MarkerEvent::Enable(full_id) = event else {
-            continue;
+        let full_id = match event {
+            MarkerEvent::Enable(full_id) => full_id,
+            MarkerEvent::Disable(full_id) => {
+                spawned.retain(|trail_id, entity| {
+                    let keep = trail_id.full_id != *full_id;
+                    if !keep {
+                        commands.entity(*entity).despawn_recursive();
+                        unloaded += 1;
+                    }
+                    keep
+                });
+                continue;
+            }
+            MarkerEvent::DisableAll => {
+                for (_, entity) in spawned.drain() {
+                    commands.entity(entity).despawn_recursive();
+                    unloaded += 1;
+                }
+                continue;
+            }
         };
 
         let Some(pack) = &packs.get(&full_id.pack_id) else {
@@ -127,63 +456,654 @@ fn show_trails(
             continue;
         };
 
-        for trail in marker
+        for (index, trail) in marker
             .trails
             .iter()
-            .filter(|trail| trail.map_id == map_id.0)
+            .enumerate()
+            .filter(|(_, trail)| trail.map_id == map_id.0)
         {
-            let iter = trail.path.iter().rev().map(|path| Vec3 {
-                x: path.x,
-                y: path.y,
-                z: -path.z,
-            });
+            let trail_id = TrailId {
+                full_id: full_id.clone(),
+                index,
+            };
+            if spawned.contains_key(&trail_id) {
+                continue;
+            }
 
-            let Some(texture) = pack.get_image(&trail.texture_file) else {
+            let Some(texture) = textures.get(
+                &full_id.pack_id,
+                trail.from_pack.as_ref(),
+                &trail.texture_file,
+            ) else {
                 warn!("Could not find texture {}", trail.texture_file);
                 continue;
             };
 
-            let material = trail_materials.add(TrailMaterial {
-                color: LinearRgba::WHITE,
+            let display = trail.display;
+            let fade = Fade::new(&display);
+            let material = TrailMaterial {
+                color: display_color(&display).into(),
                 color_texture: Some(texture),
                 alpha_mode: AlphaMode::Blend,
-                speed: 1.0,
-            });
+                speed: display.anim_speed.unwrap_or(1.0),
+                fade: fade.uniform(&fade_settings),
+            };
+            let material = cache.material(&mut trail_materials, material);
 
-            let mesh = create_trail_mesh(iter);
+            let mesh = cache
+                .meshes
+                .entry(trail_id.clone())
+                .or_insert_with(|| {
+                    let pack_id = trail.from_pack.as_ref().unwrap_or(&full_id.pack_id);
+                    let segments = if settings.repair_packs.contains(pack_id) {
+                        join_corrupt_segments(trail.segments.clone())
+                    } else {
+                        trail.segments.clone()
+                    };
+                    let segments = segments.into_iter().rev().map(|segment| {
+                        segment.into_iter().rev().map(|path| Vec3 {
+                            x: path.x,
+                            y: path.y,
+                            z: -path.z,
+                        })
+                    });
+                    meshes.add(create_trail_mesh(
+                        segments,
+                        &TrailMeshOptions {
+                            width: settings.mesh.width * display.trail_scale.unwrap_or(1.0),
+                            is_wall: display.is_wall.unwrap_or_default(),
+                            ..settings.mesh
+                        },
+                    ))
+                })
+                .clone();
 
-            commands.spawn((
-                TrailMesh,
-                MaterialMeshBundle {
-                    mesh: meshes.add(mesh),
-                    material,
-                    ..default()
-                },
-            ));
-            info!("Loaded trail: {:?}", full_id);
+            let entity = commands
+                .spawn((
+                    TrailMesh,
+                    trail_id.clone(),
+                    fade,
+                    MaterialMeshBundle {
+                        mesh,
+                        material,
+                        ..default()
+                    },
+                ))
+                .id();
+            spawned.insert(trail_id, entity);
+            loaded += 1;
         }
     }
+
+    if loaded > 0 {
+        info!("Loaded {loaded} trail(s).");
+    }
+    if unloaded > 0 {
+        info!("Unloaded {unloaded} trail(s).");
+    }
 }
 
-fn hide_trails(mut commands: Commands, q_trails: Query<Entity, With<TrailMesh>>) {
-    let mut count = 0;
+/// Trails are only for the current map. They're spawned again from the
+/// enabled markers once the next map is entered, so the cache can let go
+/// of their meshes and textures.
+fn map_exit_system(
+    mut commands: Commands,
+    mut cache: ResMut<TrailMeshCache>,
+    q_trails: Query<Entity, With<TrailMesh>>,
+) {
     for entity in &q_trails {
         commands.entity(entity).despawn_recursive();
-        count += 1;
     }
-    if count > 0 {
-        info!("Unloaded {} trails.", count);
+    cache.clear();
+}
+
+fn find_repair_file() -> Result<PathBuf> {
+    Ok(state_dir()?.join("repair_trails.ron"))
+}
+
+/// Read the packs to repair, a list like `["tw_ALL_IN_ONE.taco"]`.
+fn load_repair_system(
+    filepath: In<Result<PathBuf>>,
+    mut settings: ResMut<TrailSettings>,
+) -> Result<()> {
+    let filepath = filepath.0?;
+
+    if !std::fs::exists(&filepath).unwrap_or_default() {
+        return Ok(());
     }
+
+    let data =
+        File::open(&filepath).map_err(|err| anyhow!("Could not read {filepath:?}: {err:?}"))?;
+
+    let pack_ids: Vec<String> = ron::de::from_reader(data)
+        .map_err(|err| anyhow!("Could not deserialize {filepath:?}: {err:?}"))?;
+    settings.repair_packs = pack_ids.into_iter().map(PackId).collect();
+    Ok(())
 }
 
 pub(super) struct Plugin;
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(MaterialPlugin::<TrailMaterial>::default());
+        app.init_resource::<TrailSettings>();
+        app.init_resource::<TrailMeshCache>();
+        app.add_systems(
+            Startup,
+            find_repair_file.pipe(load_repair_system).pipe(output_error),
+        );
         app.add_systems(
             Update,
-            (hide_trails, show_trails.run_if(resource_exists::<MapId>))
-                .run_if(on_event::<MarkerEvent>()),
+            (
+                // Kept apart, as `or_else` would skip checking, and so
+                // tracking changes to, the second resource.
+                clear_cache_system.run_if(resource_exists_and_changed::<MarkerPacks>),
+                clear_cache_system.run_if(resource_changed::<TrailSettings>),
+                refade_system.run_if(resource_changed::<FadeSettings>),
+                update_trails_system
+                    .run_if(resource_exists::<MapId>)
+                    .run_if(on_event::<MarkerEvent>()),
+            )
+                .chain(),
+        );
+        app.add_systems(OnEnter(GameState::ChangingMaps), map_exit_system);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine as _;
+    use bevy::ecs::system::RunSystemOnce as _;
+    use bevy::state::app::StatesPlugin;
+    use tempfile::tempdir;
+
+    use crate::parser::pack::MarkerName;
+    use crate::parser::read_marker_pack;
+    use crate::parser::trail::to_file;
+    use crate::parser::trail::TrailData;
+
+    fn full_id(name: &str) -> FullMarkerId {
+        PackId("test".into()).with_marker(MarkerName(vec![name.into()]))
+    }
+
+    fn app() -> App {
+        let dir = tempdir().unwrap();
+        let pack_dir = dir.path().join("test");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        // A 1x1 PNG
+        std::fs::write(
+            pack_dir.join("trail.png"),
+            BASE64_STANDARD
+                .decode("iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==")
+                .unwrap(),
+        )
+        .unwrap();
+        for (file, map_id) in [("a.trl", 15), ("b.trl", 15), ("other.trl", 50)] {
+            to_file(
+                pack_dir.join(file),
+                &TrailData {
+                    version: 0,
+                    map_id,
+                    segments: vec![vec![Vec3::ONE, Vec3::splat(2.0), Vec3::splat(3.0)]],
+                },
+            )
+            .unwrap();
+        }
+        std::fs::write(
+            pack_dir.join("test.xml"),
+            r#"
+<OverlayData>
+  <MarkerCategory name="A" texture="trail.png" />
+  <MarkerCategory name="B" texture="trail.png" />
+  <POIs>
+    <Trail type="A" trailData="a.trl" />
+    <Trail type="B" trailData="b.trl" />
+    <Trail type="B" trailData="a.trl" />
+    <Trail type="B" trailData="other.trl" />
+  </POIs>
+</OverlayData>
+"#,
+        )
+        .unwrap();
+
+        let mut app = App::new();
+        app.add_plugins((
+            AssetPlugin {
+                watch_for_changes_override: Some(false),
+                ..default()
+            },
+            StatesPlugin,
+        ));
+        app.init_asset::<Image>();
+        app.init_asset::<Mesh>();
+        app.init_asset::<Shader>();
+        app.add_event::<MarkerEvent>();
+        app.add_plugins(crate::parser::textures::Plugin);
+        app.init_resource::<FadeSettings>();
+        app.insert_state(AppState::Running);
+        app.add_sub_state::<GameState>();
+        app.insert_resource(MapId(15));
+        app.insert_resource(MarkerPacks::new(HashMap::from_iter([(
+            PackId("test".into()),
+            read_marker_pack(&pack_dir).unwrap(),
+        )])));
+        app.add_plugins(Plugin);
+        // Don't read the player's own repair_trails.ron.
+        app.world_mut().resource_mut::<Schedules>().remove(Startup);
+        app
+    }
+
+    fn send(app: &mut App, events: impl IntoIterator<Item = MarkerEvent>) {
+        for event in events {
+            app.world_mut().send_event(event);
+        }
+        app.update();
+    }
+
+    fn trails(app: &mut App) -> Vec<(Entity, TrailId)> {
+        let mut trails = app
+            .world_mut()
+            .query::<(Entity, &TrailId)>()
+            .iter(app.world())
+            .map(|(entity, trail_id)| (entity, trail_id.clone()))
+            .collect::<Vec<_>>();
+        trails.sort_by_key(|(_, trail_id)| {
+            (trail_id.full_id.marker_name.to_string(), trail_id.index)
+        });
+        trails
+    }
+
+    fn trail_id(name: &str, index: usize) -> TrailId {
+        TrailId {
+            full_id: full_id(name),
+            index,
+        }
+    }
+
+    #[test]
+    fn test_toggle_one_marker() {
+        let mut app = app();
+        send(
+            &mut app,
+            [
+                MarkerEvent::Enable(full_id("A")),
+                MarkerEvent::Enable(full_id("B")),
+            ],
+        );
+        let spawned = trails(&mut app);
+        // B's trail on another map isn't spawned.
+        assert_eq!(
+            spawned
+                .iter()
+                .map(|(_, trail_id)| trail_id.clone())
+                .collect::<Vec<_>>(),
+            vec![trail_id("A", 0), trail_id("B", 0), trail_id("B", 1)]
+        );
+
+        // Disabling A leaves B's trails untouched.
+        send(&mut app, [MarkerEvent::Disable(full_id("A"))]);
+        assert_eq!(trails(&mut app), spawned[1..].to_vec());
+
+        // Enabling B again doesn't spawn it twice.
+        send(
+            &mut app,
+            [
+                MarkerEvent::Enable(full_id("A")),
+                MarkerEvent::Enable(full_id("B")),
+                MarkerEvent::Enable(full_id("A")),
+            ],
         );
+        let respawned = trails(&mut app);
+        assert_eq!(respawned.len(), 3);
+        assert_eq!(respawned[1..], spawned[1..]);
+    }
+
+    #[test]
+    fn test_event_order() {
+        let mut app = app();
+        send(
+            &mut app,
+            [
+                MarkerEvent::Enable(full_id("A")),
+                MarkerEvent::Disable(full_id("A")),
+                MarkerEvent::Enable(full_id("B")),
+            ],
+        );
+        assert_eq!(
+            trails(&mut app)
+                .into_iter()
+                .map(|(_, trail_id)| trail_id)
+                .collect::<Vec<_>>(),
+            vec![trail_id("B", 0), trail_id("B", 1)]
+        );
+
+        send(
+            &mut app,
+            [MarkerEvent::DisableAll, MarkerEvent::Enable(full_id("A"))],
+        );
+        assert_eq!(
+            trails(&mut app)
+                .into_iter()
+                .map(|(_, trail_id)| trail_id)
+                .collect::<Vec<_>>(),
+            vec![trail_id("A", 0)]
+        );
+
+        send(&mut app, [MarkerEvent::DisableAll]);
+        assert!(trails(&mut app).is_empty());
+    }
+
+    #[test]
+    fn test_mesh_cache() {
+        let mut app = app();
+        let enable_all = [
+            MarkerEvent::Enable(full_id("A")),
+            MarkerEvent::Enable(full_id("B")),
+        ];
+        send(&mut app, enable_all.clone());
+        let mesh_of = |app: &mut App| {
+            app.world_mut()
+                .query::<(&TrailId, &Handle<Mesh>)>()
+                .iter(app.world())
+                .map(|(trail_id, mesh)| (trail_id.clone(), mesh.id()))
+                .collect::<HashMap<_, _>>()
+        };
+        let meshes = mesh_of(&mut app);
+        assert_eq!(app.world().resource::<Assets<Mesh>>().len(), 3);
+        // Every trail has the same texture, color and speed.
+        assert_eq!(app.world().resource::<Assets<TrailMaterial>>().len(), 1);
+
+        send(&mut app, [MarkerEvent::DisableAll]);
+        send(&mut app, enable_all.clone());
+        assert_eq!(mesh_of(&mut app), meshes);
+        assert_eq!(app.world().resource::<Assets<Mesh>>().len(), 3);
+
+        // A reload throws the cache away.
+        send(&mut app, [MarkerEvent::DisableAll]);
+        app.world_mut().resource_mut::<MarkerPacks>().set_changed();
+        send(&mut app, enable_all.clone());
+        assert!(mesh_of(&mut app)
+            .values()
+            .all(|id| !meshes.values().any(|old| old == id)));
+
+        // So do new settings.
+        let cached = |app: &mut App| {
+            let cache = app.world().resource::<TrailMeshCache>();
+            cache.meshes.len() + cache.materials.len()
+        };
+        assert_eq!(cached(&mut app), 4);
+        app.world_mut()
+            .resource_mut::<TrailSettings>()
+            .repair_packs
+            .insert(PackId("test".into()));
+        app.update();
+        assert_eq!(cached(&mut app), 0);
+
+        // Nothing is kept once the map is left.
+        send(&mut app, [MarkerEvent::DisableAll]);
+        send(&mut app, enable_all);
+        app.world_mut()
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::InGame);
+        app.update();
+        assert_eq!(cached(&mut app), 4);
+        app.world_mut()
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::ChangingMaps);
+        app.update();
+        assert_eq!(cached(&mut app), 0);
+        assert!(trails(&mut app).is_empty());
+    }
+
+    #[test]
+    fn test_load_repair_packs() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("repair_trails.ron");
+        let mut world = World::new();
+        world.init_resource::<TrailSettings>();
+
+        // A missing file leaves the defaults.
+        world
+            .run_system_once_with(Ok(path.clone()), load_repair_system)
+            .unwrap();
+        assert!(world.resource::<TrailSettings>().repair_packs.is_empty());
+
+        std::fs::write(&path, r#"["tw_ALL_IN_ONE.taco"]"#).unwrap();
+        world
+            .run_system_once_with(Ok(path), load_repair_system)
+            .unwrap();
+        assert_eq!(
+            world.resource::<TrailSettings>().repair_packs,
+            HashSet::from_iter([PackId("tw_ALL_IN_ONE.taco".into())])
+        );
+    }
+
+    fn mesh_data(mesh: &Mesh) -> (Vec<Vec3>, Vec<Vec3>, Vec<Vec2>, Vec<u32>) {
+        use bevy::render::mesh::MeshVertexAttribute;
+        use bevy::render::mesh::VertexAttributeValues;
+
+        let float3 = |attribute: MeshVertexAttribute| {
+            mesh.attribute(attribute)
+                .and_then(VertexAttributeValues::as_float3)
+                .unwrap()
+                .iter()
+                .map(|v| Vec3::from_array(*v))
+                .collect::<Vec<_>>()
+        };
+        let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0)
+        else {
+            panic!("No UVs");
+        };
+        let Some(Indices::U32(indices)) = mesh.indices() else {
+            panic!("No indices");
+        };
+        (
+            float3(Mesh::ATTRIBUTE_POSITION),
+            float3(Mesh::ATTRIBUTE_NORMAL),
+            uvs.iter().map(|v| Vec2::from_array(*v)).collect(),
+            indices.clone(),
+        )
+    }
+
+    /// Every quad starts on the edge the previous one ended on.
+    fn assert_continuous(indices: &[u32]) {
+        for (prev, next) in indices.chunks(6).zip(indices.chunks(6).skip(1)) {
+            assert_eq!([next[0], next[1]], [prev[4], prev[2]]);
+        }
+    }
+
+    #[test]
+    fn test_mesh_straight() {
+        let mesh = create_trail_mesh(
+            [[
+                Vec3::ZERO,
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(4.0, 0.0, 0.0),
+            ]],
+            &TrailMeshOptions {
+                uv_per_metre: 0.5,
+                ..default()
+            },
+        );
+        let (positions, normals, uvs, indices) = mesh_data(&mesh);
+        assert_eq!(positions.len(), 6);
+        assert_eq!(indices.len(), 12);
+        assert_continuous(&indices);
+        assert_eq!(positions[0], Vec3::new(0.0, 0.0, -0.5));
+        assert_eq!(positions[1], Vec3::new(0.0, 0.0, 0.5));
+        assert_eq!(positions[5], Vec3::new(4.0, 0.0, 0.5));
+        assert!(normals.iter().all(|normal| *normal == Vec3::Y));
+        assert_eq!(uvs[2], Vec2::new(0.0, 1.0));
+        assert_eq!(uvs[5], Vec2::new(1.0, 2.0));
+    }
+
+    #[test]
+    fn test_mesh_segments() {
+        let mesh = create_trail_mesh(
+            [
+                vec![Vec3::ZERO, Vec3::X],
+                // Duplicate points are dropped, leaving nothing to draw.
+                vec![Vec3::Y, Vec3::Y],
+                vec![Vec3::Z, Vec3::Z, Vec3::ONE],
+            ],
+            &default(),
+        );
+        let (positions, _, uvs, indices) = mesh_data(&mesh);
+        assert_eq!(positions.len(), 8);
+        assert_eq!(indices.len(), 12);
+        assert!(indices[6..].iter().all(|i| *i >= 4));
+        // Distance starts over in every segment.
+        assert_eq!(uvs[4].y, 0.0);
+    }
+
+    #[test]
+    fn test_mesh_miter() {
+        let corner = Vec3::new(10.0, 0.0, 0.0);
+        let mesh = create_trail_mesh(
+            [[Vec3::ZERO, corner, Vec3::new(10.0, 0.0, 10.0)]],
+            &default(),
+        );
+        let (positions, _, _, indices) = mesh_data(&mesh);
+        assert_eq!(positions.len(), 6);
+        assert_continuous(&indices);
+        // Both edges meet at the corner, half a width from either segment.
+        assert!((positions[2] - corner).abs_diff_eq(Vec3::new(0.5, 0.0, -0.5), 1e-5));
+        assert!((positions[3] - corner).abs_diff_eq(Vec3::new(-0.5, 0.0, 0.5), 1e-5));
+    }
+
+    #[test]
+    fn test_mesh_bevel() {
+        let corner = Vec3::new(10.0, 0.0, 0.0);
+        let path = [Vec3::ZERO, corner, Vec3::new(10.0, 0.0, 10.0)];
+        let mesh = create_trail_mesh(
+            [path],
+            &TrailMeshOptions {
+                join: TrailJoin::Bevel,
+                ..default()
+            },
+        );
+        let (positions, _, _, indices) = mesh_data(&mesh);
+        assert_eq!(positions.len(), 8);
+        assert_eq!(indices.len(), 18);
+        assert_continuous(&indices);
+        // The outside of the corner is cut off...
+        assert!((positions[2] - corner).abs_diff_eq(Vec3::new(0.0, 0.0, -0.5), 1e-5));
+        assert!((positions[4] - corner).abs_diff_eq(Vec3::new(0.5, 0.0, 0.0), 1e-5));
+        // ...and the inside is shared.
+        assert!((positions[3] - corner).abs_diff_eq(Vec3::new(-0.5, 0.0, 0.5), 1e-5));
+        assert_eq!(positions[3], positions[5]);
+
+        // Sharp corners are bevelled even when mitring.
+        let mesh = create_trail_mesh([[Vec3::ZERO, corner, Vec3::new(0.0, 0.0, 1.0)]], &default());
+        let (positions, _, _, indices) = mesh_data(&mesh);
+        assert_eq!(positions.len(), 8);
+        assert_continuous(&indices);
+        assert!(positions
+            .iter()
+            .all(|p| p.distance(corner) < 10.0 * 4.0 * 0.5));
+    }
+
+    #[test]
+    fn test_mesh_subdivide() {
+        let path = [
+            Vec3::ZERO,
+            Vec3::new(4.0, 0.0, 0.0),
+            Vec3::new(8.0, 0.0, 4.0),
+        ];
+        let points = subdivide(&path, 3);
+        assert_eq!(points.len(), 9);
+        // The spline goes through every point of the path.
+        assert_eq!(points[0], path[0]);
+        assert_eq!(points[4], path[1]);
+        assert_eq!(points[8], path[2]);
+        assert_eq!(subdivide(&path, 0), path);
+
+        let mesh = create_trail_mesh(
+            [path],
+            &TrailMeshOptions {
+                subdivisions: 3,
+                ..default()
+            },
+        );
+        let (positions, _, uvs, indices) = mesh_data(&mesh);
+        assert_eq!(positions.len(), 18);
+        assert_eq!(indices.len(), 48);
+        assert_continuous(&indices);
+        assert!(uvs.windows(2).all(|uv| uv[0].y <= uv[1].y));
+    }
+
+    #[test]
+    fn test_mesh_steep() {
+        // Straight up a wall and over the top.
+        let path = [
+            Vec3::ZERO,
+            Vec3::new(0.0, 10.0, 0.0),
+            Vec3::new(0.1, 20.0, 0.0),
+            Vec3::new(10.0, 20.0, 0.0),
+        ];
+        let mesh = create_trail_mesh([path], &default());
+        let (positions, normals, _, indices) = mesh_data(&mesh);
+        assert_continuous(&indices);
+        assert!(positions.iter().all(|p| p.is_finite()));
+        assert!(normals.iter().all(|n| n.is_normalized()));
+        // The ribbon doesn't twist around between points.
+        assert!(normals.windows(2).all(|n| n[0].dot(n[1]) > 0.5));
+        // Normals are perpendicular to the path.
+        let tangent = (path[3] - path[2]).normalize();
+        assert!(normals[6].dot(tangent).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_mesh_wall() {
+        let mesh = create_trail_mesh(
+            [[Vec3::ZERO, Vec3::X, Vec3::new(1.0, 0.0, 1.0)]],
+            &TrailMeshOptions {
+                is_wall: true,
+                ..default()
+            },
+        );
+        let (positions, normals, _, indices) = mesh_data(&mesh);
+        assert_eq!(positions.len(), 6);
+        assert_continuous(&indices);
+        assert_eq!(positions[0], Vec3::new(0.0, -0.5, 0.0));
+        assert_eq!(positions[1], Vec3::new(0.0, 0.5, 0.0));
+        assert!(normals.iter().all(|normal| normal.y == 0.0));
+    }
+
+    #[test]
+    fn test_refade() {
+        let mut app = app();
+        send(
+            &mut app,
+            [
+                MarkerEvent::Enable(full_id("A")),
+                MarkerEvent::Enable(full_id("B")),
+            ],
+        );
+        let fades = |app: &mut App| {
+            let materials = app
+                .world_mut()
+                .query::<&Handle<TrailMaterial>>()
+                .iter(app.world())
+                .map(|handle| handle.id())
+                .collect::<HashSet<_>>();
+            let assets = app.world().resource::<Assets<TrailMaterial>>();
+            materials
+                .into_iter()
+                .map(|id| assets.get(id).unwrap().fade)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fades(&mut app), vec![Vec2::new(150.0, 200.0)]);
+
+        *app.world_mut().resource_mut::<FadeSettings>() = FadeSettings {
+            near: None,
+            far: Some(50.0),
+        };
+        app.update();
+        assert_eq!(fades(&mut app), vec![Vec2::new(50.0, 50.0)]);
+        // The material for the old fade isn't kept around.
+        assert_eq!(app.world().resource::<TrailMeshCache>().materials.len(), 1);
     }
 }
@@ -0,0 +1,182 @@
+use bevy::prelude::*;
+use bevy::render::primitives::Aabb;
+use orrient_core::prelude::*;
+
+use crate::parser::model::DisplayAttributes;
+
+/// TacO measures `fadeNear` and `fadeFar` in inches.
+const INCHES_PER_METRE: f32 = 39.37;
+
+/// Fade distances for markers that don't set `fadeNear` and `fadeFar`,
+/// in metres.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct FadeSettings {
+    /// Where markers start fading out. Unset fades them out all at once
+    /// at `far`.
+    pub near: Option<f32>,
+    /// Where markers have faded out completely and stop being drawn.
+    /// Unset never fades them out.
+    pub far: Option<f32>,
+}
+
+impl Default for FadeSettings {
+    fn default() -> Self {
+        Self {
+            near: Some(150.0),
+            far: Some(200.0),
+        }
+    }
+}
+
+/// The `fadeNear` and `fadeFar` of a POI or trail, in metres. A
+/// negative `fadeFar` never fades the marker out.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Fade {
+    pub near: Option<f32>,
+    pub far: Option<f32>,
+}
+
+impl Fade {
+    pub fn new(display: &DisplayAttributes) -> Self {
+        Self {
+            near: display.fade_near.map(|near| near / INCHES_PER_METRE),
+            far: display.fade_far.map(|far| far / INCHES_PER_METRE),
+        }
+    }
+
+    /// The distances between which the marker fades out, or `None` if
+    /// it never does.
+    pub fn range(&self, settings: &FadeSettings) -> Option<(f32, f32)> {
+        let far = self.far.or(settings.far).filter(|far| *far > 0.0)?;
+        let near = match self.near {
+            Some(near) if near < 0.0 => far,
+            Some(near) => near,
+            None => settings.near.unwrap_or(far),
+        };
+        Some((near.min(far), far))
+    }
+
+    /// The `fade` uniform of the trail and POI shaders: the range the
+    /// marker fades out over, or `-1` if it never does.
+    pub(super) fn uniform(&self, settings: &FadeSettings) -> Vec2 {
+        match self.range(settings) {
+            Some((near, far)) => Vec2::new(near, far),
+            None => Vec2::splat(-1.0),
+        }
+    }
+}
+
+/// Distance from `point` to the closest part of a marker. Markers
+/// without bounds are treated as a point.
+pub(super) fn distance(point: Vec3, transform: &GlobalTransform, aabb: Option<&Aabb>) -> f32 {
+    let Some(aabb) = aabb else {
+        return transform.translation().distance(point);
+    };
+    let local = transform.affine().inverse().transform_point3(point);
+    let closest = local.clamp(aabb.min().into(), aabb.max().into());
+    transform.transform_point(closest).distance(point)
+}
+
+/// Stop drawing markers that are past their `fadeFar`.
+fn cull_system(
+    settings: Res<FadeSettings>,
+    camera: Query<&Transform, With<Camera3d>>,
+    mut markers: Query<(&Fade, &GlobalTransform, Option<&Aabb>, &mut Visibility)>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+
+    for (fade, transform, aabb, mut visibility) in &mut markers {
+        let culled = fade
+            .range(&settings)
+            .is_some_and(|(_, far)| distance(camera.translation, transform, aabb) > far);
+        visibility.set_if_neq(if culled {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        });
+    }
+}
+
+pub(super) struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FadeSettings>();
+        app.add_systems(Update, cull_system.run_if(in_state(GameState::InGame)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fade_range() {
+        let settings = FadeSettings::default();
+        let fade = |near: Option<f32>, far: Option<f32>| Fade { near, far };
+
+        assert_eq!(fade(None, None).range(&settings), Some((150.0, 200.0)));
+        assert_eq!(
+            fade(Some(10.0), Some(20.0)).range(&settings),
+            Some((10.0, 20.0))
+        );
+        // Markers that never fade ignore the defaults.
+        assert_eq!(fade(None, Some(-1.0)).range(&settings), None);
+        // Without a `fadeNear` they disappear all at once.
+        assert_eq!(
+            fade(Some(-1.0), Some(20.0)).range(&settings),
+            Some((20.0, 20.0))
+        );
+        assert_eq!(
+            fade(Some(30.0), Some(20.0)).range(&settings),
+            Some((20.0, 20.0))
+        );
+        assert_eq!(fade(Some(10.0), None).range(&settings), Some((10.0, 200.0)));
+
+        let settings = FadeSettings {
+            near: None,
+            far: None,
+        };
+        assert_eq!(fade(None, None).range(&settings), None);
+        assert_eq!(fade(None, Some(20.0)).range(&settings), Some((20.0, 20.0)));
+
+        let display = DisplayAttributes {
+            fade_near: Some(3937.0),
+            fade_far: Some(7874.0),
+            ..default()
+        };
+        let fade = Fade::new(&display);
+        assert!((fade.near.unwrap() - 100.0).abs() < 1e-3);
+        assert!((fade.far.unwrap() - 200.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_fade_uniform() {
+        let settings = FadeSettings::default();
+        let fade = Fade {
+            near: Some(10.0),
+            far: Some(20.0),
+        };
+        assert_eq!(fade.uniform(&settings), Vec2::new(10.0, 20.0));
+
+        let fade = Fade {
+            near: None,
+            far: Some(-1.0),
+        };
+        assert_eq!(fade.uniform(&settings), Vec2::splat(-1.0));
+    }
+
+    #[test]
+    fn test_distance() {
+        let transform = GlobalTransform::from_translation(Vec3::new(10.0, 0.0, 0.0));
+        let aabb = Aabb::from_min_max(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+
+        assert_eq!(distance(Vec3::ZERO, &transform, None), 10.0);
+        assert_eq!(distance(Vec3::ZERO, &transform, Some(&aabb)), 9.0);
+        assert_eq!(
+            distance(Vec3::new(10.0, 0.5, 0.0), &transform, Some(&aabb)),
+            0.0
+        );
+    }
+}
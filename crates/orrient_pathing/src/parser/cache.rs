@@ -0,0 +1,326 @@
+//! On-disk cache of parsed marker packs.
+//!
+//! Parsing a large pack means decompressing and reading every XML, TRL
+//! and image in it, so the built [`MarkerPack`] is written to the cache
+//! dir after parsing. The next launch reads it back as long as the
+//! pack's [`CacheKey`] still matches.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use anyhow::bail;
+use anyhow::Context as _;
+use anyhow::Result;
+use bevy::utils::HashMap;
+use serde::Deserialize;
+use serde::Serialize;
+use slab_tree::NodeId;
+use slab_tree::Tree;
+
+use super::diagnostics::PackDiagnostics;
+use super::pack::Marker;
+use super::pack::MarkerPack;
+use super::pack::Orphans;
+
+/// Bump this whenever the layout of [`Marker`] or anything it contains
+/// changes, or parsing changes what ends up in it, so old caches are
+/// thrown away instead of misread or left stale.
+const CACHE_VERSION: u32 = 10;
+
+/// Identifies the exact contents of a marker pack on disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct CacheKey {
+    size: u64,
+    modified: u128,
+    hash: u64,
+}
+
+impl CacheKey {
+    /// Build the key for a pack archive or an unpacked pack directory.
+    pub(super) fn new(path: &Path) -> Result<Self> {
+        let mut key = Self {
+            size: 0,
+            modified: 0,
+            hash: Fnv64::OFFSET_BASIS,
+        };
+        key.add(path, path)?;
+        Ok(key)
+    }
+
+    fn add(&mut self, root: &Path, path: &Path) -> Result<()> {
+        if path.is_dir() {
+            let mut paths = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            paths.sort();
+            for path in paths {
+                self.add(root, &path)?;
+            }
+            return Ok(());
+        }
+
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+        self.size += metadata.len();
+        self.modified = self.modified.max(modified);
+
+        let mut hasher = Fnv64(self.hash);
+        hasher.write(path.strip_prefix(root)?.to_string_lossy().as_bytes());
+        let mut file = File::open(path)?;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let len = file.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            hasher.write(&buf[..len]);
+        }
+        self.hash = hasher.0;
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a
+struct Fnv64(u64);
+
+impl Fnv64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x00000100000001b3;
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheHeader {
+    version: u32,
+    key: CacheKey,
+}
+
+/// The marker tree flattened in pre-order. Every node's parent comes
+/// before it, so the tree can be rebuilt in a single pass.
+#[derive(Serialize, Deserialize)]
+struct CachedPack<M> {
+    nodes: Vec<CachedNode<M>>,
+    images: Vec<CachedImage>,
+    diagnostics: PackDiagnostics,
+    orphans: Orphans,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedNode<M> {
+    parent: Option<usize>,
+    marker: M,
+}
+
+/// An image file as it was in the pack.
+#[derive(Serialize, Deserialize)]
+struct CachedImage {
+    file_path: String,
+    data: Vec<u8>,
+}
+
+/// Where the cache for the pack at `path` is stored in `cache_dir`.
+pub(super) fn cache_file(cache_dir: &Path, path: &Path) -> Result<PathBuf> {
+    let filename = path
+        .file_name()
+        .with_context(|| format!("Could not determine filename in {path:?}"))?
+        .to_string_lossy();
+    Ok(cache_dir.join(format!("{filename}.bin")))
+}
+
+/// Read a cached pack. Fails if the cache is missing, was written by a
+/// different version, or doesn't match `key`.
+pub(super) fn read(cache_file: &Path, key: &CacheKey) -> Result<MarkerPack> {
+    let mut reader = BufReader::new(File::open(cache_file)?);
+
+    let header: CacheHeader = bincode::deserialize_from(&mut reader)?;
+    if header.version != CACHE_VERSION {
+        bail!("Cache version {} is out of date", header.version);
+    }
+    if header.key != *key {
+        bail!("Cache key does not match");
+    }
+
+    let cached: CachedPack<Marker> = bincode::deserialize_from(&mut reader)?;
+
+    let mut tree = Tree::new();
+    let mut node_ids: Vec<NodeId> = Vec::with_capacity(cached.nodes.len());
+    for node in cached.nodes {
+        let node_id = match node.parent {
+            None => tree.set_root(node.marker),
+            Some(parent) => tree
+                .get_mut(*node_ids.get(parent).context("Invalid parent in cache")?)
+                .context("Invalid parent in cache")?
+                .append(node.marker)
+                .node_id(),
+        };
+        node_ids.push(node_id);
+    }
+    if tree.root().is_none() {
+        bail!("Cache is empty");
+    }
+
+    let mut pack = MarkerPack::new(tree);
+    pack.diagnostics = cached.diagnostics;
+    pack.orphans = cached.orphans;
+    for image in cached.images {
+        pack.image_files.insert(image.file_path, image.data);
+    }
+    Ok(pack)
+}
+
+/// Write `pack` to the cache.
+pub(super) fn write(cache_file: &Path, key: &CacheKey, pack: &MarkerPack) -> Result<()> {
+    let mut indices: HashMap<NodeId, usize> = Default::default();
+    let mut nodes = Vec::new();
+    for node in pack
+        .root()
+        .context("Pack has no root")?
+        .traverse_pre_order()
+    {
+        let parent = node.parent().map(|parent| indices[&parent.node_id()]);
+        indices.insert(node.node_id(), nodes.len());
+        nodes.push(CachedNode {
+            parent,
+            marker: node.data(),
+        });
+    }
+
+    let images = pack
+        .image_files
+        .iter()
+        .map(|(file_path, data)| CachedImage {
+            file_path: file_path.clone(),
+            data: data.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(dir) = cache_file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    // Write to a temporary file first so an interrupted write never
+    // leaves a truncated cache behind.
+    let tmp_file = cache_file.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_file)?);
+    bincode::serialize_into(
+        &mut writer,
+        &CacheHeader {
+            version: CACHE_VERSION,
+            key: *key,
+        },
+    )?;
+    bincode::serialize_into(
+        &mut writer,
+        &CachedPack {
+            nodes,
+            images,
+            diagnostics: pack.diagnostics.clone(),
+            orphans: pack.orphans.clone(),
+        },
+    )?;
+    writer.into_inner()?.sync_all()?;
+    std::fs::rename(tmp_file, cache_file)?;
+    Ok(())
+}
+
+/// Serde helpers for `typed_path` paths, which don't implement serde
+/// themselves.
+pub(super) mod unix_path {
+    use serde::Deserialize as _;
+    use serde::Deserializer;
+    use serde::Serialize as _;
+    use serde::Serializer;
+    use typed_path::Utf8PathBuf;
+    use typed_path::Utf8UnixEncoding;
+
+    pub fn serialize<S: Serializer>(
+        path: &Option<Utf8PathBuf<Utf8UnixEncoding>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        path.as_ref()
+            .map(|path| path.as_str())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Utf8PathBuf<Utf8UnixEncoding>>, D::Error> {
+        Ok(Option::<String>::deserialize(deserializer)?.map(Utf8PathBuf::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    const XML: &str = r#"
+<OverlayData>
+  <MarkerCategory name="A" DisplayName="Item A" iconFile="icon.png">
+    <MarkerCategory name="B" DisplayName="Item A.B" behavior="2" />
+  </MarkerCategory>
+  <POIs>
+    <POI MapID="15" xpos="100.0" ypos="100.0" zpos="-100.0" type="A.B" />
+  </POIs>
+</OverlayData>
+"#;
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let dir = tempdir().unwrap();
+        let pack_dir = dir.path().join("test");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        std::fs::write(pack_dir.join("test.xml"), XML).unwrap();
+
+        let pack = super::super::read_marker_pack(&pack_dir).unwrap();
+        let key = CacheKey::new(&pack_dir).unwrap();
+        let cache_file = cache_file(dir.path(), &pack_dir).unwrap();
+        write(&cache_file, &key, &pack).unwrap();
+
+        let cached = read(&cache_file, &key).unwrap();
+        assert_eq!(cached.id(), pack.id());
+
+        let node_id = cached.find_by_name(["A", "B"].into_iter()).unwrap();
+        let marker = cached.get(node_id).unwrap().data();
+        assert_eq!(marker.label, "Item A.B");
+        assert_eq!(marker.pois.len(), 1);
+        assert_eq!(marker.pois[0].guid, {
+            let node_id = pack.find_by_name(["A", "B"].into_iter()).unwrap();
+            pack.get(node_id).unwrap().data().pois[0].guid
+        });
+        assert_eq!(
+            marker.icon_file.as_ref().map(|path| path.as_str()),
+            Some("icon.png")
+        );
+    }
+
+    #[test]
+    fn test_cache_invalidated() {
+        let dir = tempdir().unwrap();
+        let pack_dir = dir.path().join("test");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        std::fs::write(pack_dir.join("test.xml"), XML).unwrap();
+
+        let pack = super::super::read_marker_pack(&pack_dir).unwrap();
+        let key = CacheKey::new(&pack_dir).unwrap();
+        let cache_file = cache_file(dir.path(), &pack_dir).unwrap();
+        write(&cache_file, &key, &pack).unwrap();
+
+        std::fs::write(pack_dir.join("more.xml"), XML).unwrap();
+        let new_key = CacheKey::new(&pack_dir).unwrap();
+        assert_ne!(key, new_key);
+        assert!(read(&cache_file, &new_key).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Problems found while parsing a marker pack.
+//!
+//! Instead of logging as it goes, the parser records everything it had
+//! to skip or guess at in the pack's [`PackDiagnostics`], so pack
+//! authors can get a full list of broken references.
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::pack::PoiGuid;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Severity {
+    /// Something was ignored or a default was used.
+    Warning,
+    /// Part of the pack couldn't be loaded.
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => "warning".fmt(f),
+            Severity::Error => "error".fmt(f),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DiagnosticKind {
+    /// An XML element that isn't part of the format.
+    UnknownTag(String),
+    /// A known tag that couldn't be parsed, e.g. it's missing a
+    /// required attribute.
+    InvalidTag { tag: String, error: String },
+    /// An attribute key or value that isn't valid UTF-8.
+    NonUtf8Attribute,
+    /// A `GUID` attribute that isn't a base64 encoded 16 byte value.
+    InvalidGuid(String),
+    /// A POI that only sets some of `xpos`, `ypos` and `zpos`.
+    InvalidPosition(Option<String>),
+    /// A `behavior` attribute outside of the known range.
+    UnknownBehavior(u8),
+    /// `behavior="4"` without a `resetLength`.
+    MissingResetLength,
+    /// A value in a filter attribute like `profession` or `mount` that
+    /// isn't known. The rest of the list is still used.
+    UnknownFilter { key: String, value: String },
+    /// Two POIs with the same GUID. Only the first is kept.
+    DuplicateGuid { id: String, guid: PoiGuid },
+    /// A POI or trail whose `type` doesn't name a category in its own
+    /// pack. It's still shown if another pack defines the category.
+    MissingMarker(String),
+    /// A trail whose `trailData` file isn't in the pack.
+    MissingTrailData { id: String, trail_file: String },
+    /// A trail without a texture on itself or its category.
+    MissingTexture(String),
+    /// An `iconFile` or `texture` that isn't in the pack.
+    MissingFile(String),
+    /// A trail whose `trailData` file has no points.
+    EmptyTrail { id: String, trail_file: String },
+    /// A file that exists more than once in the pack.
+    DuplicateFile,
+    /// A `.trl` file that couldn't be read.
+    InvalidTrailData(String),
+    /// An image that couldn't be decoded.
+    InvalidImage(String),
+    /// Malformed XML. The parser skips ahead to the next element it can
+    /// read, or the end of the file.
+    XmlError(String),
+}
+
+impl DiagnosticKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            DiagnosticKind::UnknownTag(_)
+            | DiagnosticKind::NonUtf8Attribute
+            | DiagnosticKind::InvalidGuid(_)
+            | DiagnosticKind::UnknownBehavior(_)
+            | DiagnosticKind::MissingResetLength
+            | DiagnosticKind::UnknownFilter { .. }
+            | DiagnosticKind::MissingMarker(_)
+            | DiagnosticKind::DuplicateFile => Severity::Warning,
+            DiagnosticKind::InvalidTag { .. }
+            | DiagnosticKind::DuplicateGuid { .. }
+            | DiagnosticKind::InvalidPosition(_)
+            | DiagnosticKind::MissingTrailData { .. }
+            | DiagnosticKind::MissingTexture(_)
+            | DiagnosticKind::MissingFile(_)
+            | DiagnosticKind::EmptyTrail { .. }
+            | DiagnosticKind::InvalidTrailData(_)
+            | DiagnosticKind::InvalidImage(_)
+            | DiagnosticKind::XmlError(_) => Severity::Error,
+        }
+    }
+}
+
+impl std::fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticKind::UnknownTag(tag) => write!(f, "Unknown tag `{tag}`"),
+            DiagnosticKind::InvalidTag { tag, error } => write!(f, "Invalid `{tag}`: {error}"),
+            DiagnosticKind::NonUtf8Attribute => write!(f, "Attribute is not UTF-8 encoded"),
+            DiagnosticKind::InvalidGuid(guid) => write!(f, "Invalid GUID `{guid}`"),
+            DiagnosticKind::InvalidPosition(Some(id)) => {
+                write!(f, "POI has an invalid position: {id}")
+            }
+            DiagnosticKind::InvalidPosition(None) => write!(f, "POI has an invalid position"),
+            DiagnosticKind::UnknownBehavior(behavior) => write!(f, "Unknown behavior `{behavior}`"),
+            DiagnosticKind::MissingResetLength => {
+                write!(f, "Behavior `ReappearAfterTime` is missing `resetLength`")
+            }
+            DiagnosticKind::UnknownFilter { key, value } => {
+                write!(f, "Unknown `{key}` value `{value}`")
+            }
+            DiagnosticKind::DuplicateGuid { id, guid } => {
+                write!(f, "Duplicate POI GUID {guid} for POI {id}")
+            }
+            DiagnosticKind::MissingMarker(id) => write!(f, "Could not find Marker for {id}"),
+            DiagnosticKind::MissingTrailData { id, trail_file } => {
+                write!(f, "Trail data `{trail_file}` not found for {id}")
+            }
+            DiagnosticKind::MissingTexture(id) => write!(f, "Trail has no texture: {id}"),
+            DiagnosticKind::MissingFile(file) => write!(f, "File `{file}` not found"),
+            DiagnosticKind::EmptyTrail { id, trail_file } => {
+                write!(f, "Trail data `{trail_file}` for {id} has no points")
+            }
+            DiagnosticKind::DuplicateFile => write!(f, "File already exists"),
+            DiagnosticKind::InvalidTrailData(error) => write!(f, "Invalid trail data: {error}"),
+            DiagnosticKind::InvalidImage(error) => write!(f, "Invalid image: {error}"),
+            DiagnosticKind::XmlError(error) => write!(f, "Malformed XML: {error}"),
+        }
+    }
+}
+
+/// Where in a pack something was read from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    /// Byte offset into `file`, when it's a text file.
+    pub position: Option<u64>,
+}
+
+impl Location {
+    pub fn new(file: impl Into<String>, position: u64) -> Self {
+        Self {
+            file: file.into(),
+            position: Some(position),
+        }
+    }
+
+    pub fn file(file: impl Into<String>) -> Self {
+        Self {
+            file: file.into(),
+            position: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Path of the file in the pack.
+    pub file: String,
+    /// Byte offset into `file`, when it's a text file.
+    pub position: Option<u64>,
+    pub kind: DiagnosticKind,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.file)?;
+        if let Some(position) = self.position {
+            write!(f, "@{position}")?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+/// Every problem found while parsing a single marker pack.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PackDiagnostics(Vec<Diagnostic>);
+
+impl PackDiagnostics {
+    pub(super) fn push(&mut self, location: &Location, kind: DiagnosticKind) {
+        self.0.push(Diagnostic {
+            severity: kind.severity(),
+            file: location.file.clone(),
+            position: location.position,
+            kind,
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &Diagnostic> {
+        self.iter()
+            .filter(move |diagnostic| diagnostic.severity == severity)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.with_severity(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.with_severity(Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    /// Diagnostics found in the file at `file` in the pack.
+    pub fn in_file<'a>(&'a self, file: &'a str) -> impl Iterator<Item = &'a Diagnostic> {
+        self.iter()
+            .filter(move |diagnostic| diagnostic.file == file)
+    }
+}
@@ -0,0 +1,388 @@
+//! Write a [`MarkerPack`] back out in the TacO format.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Context as _;
+use anyhow::Result;
+use quick_xml::events::BytesDecl;
+use quick_xml::events::BytesEnd;
+use quick_xml::events::BytesStart;
+use quick_xml::events::Event;
+use quick_xml::Writer;
+use slab_tree::NodeRef;
+use typed_path::Utf8PathBuf;
+use typed_path::Utf8UnixEncoding;
+use zip::write::SimpleFileOptions;
+use zip::ZipArchive;
+use zip::ZipWriter;
+
+use super::filter::Filters;
+use super::model::Behavior;
+use super::model::DisplayAttributes;
+use super::model::MarkerKind;
+use super::pack::Marker;
+use super::pack::MarkerPack;
+use super::pack::Poi;
+use super::trail;
+use super::trail::TrailData;
+
+/// Write the category tree, POIs and trails of `pack` as TacO
+/// `OverlayData` XML. Trails reference `.trl` files that are only
+/// written by [`write_taco`].
+pub fn write_xml<W: Write>(pack: &MarkerPack, output: W) -> Result<()> {
+    write_overlay_data(pack, output)?;
+    Ok(())
+}
+
+/// Package `pack` as a `.taco` archive at `path`. The XML and trail
+/// files are written from `pack`. Every other file, like icons and
+/// textures, is copied from the pack's `source` archive or directory.
+pub fn write_taco(pack: &MarkerPack, source: &Path, path: &Path) -> Result<()> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let options = SimpleFileOptions::default();
+
+    let mut xml = Vec::new();
+    let trails = write_overlay_data(pack, &mut xml)?;
+    let stem = Path::new(pack.id())
+        .file_stem()
+        .context("Pack has no name")?
+        .to_string_lossy();
+    zip.start_file(format!("{stem}.xml"), options)?;
+    zip.write_all(&xml)?;
+
+    for (file_path, trail_data) in trails {
+        zip.start_file(file_path, options)?;
+        trail::write(&trail_data, &mut zip)?;
+    }
+
+    if source.is_dir() {
+        for (file_path, path) in super::dir_files(source)? {
+            if is_asset(&file_path) {
+                zip.start_file(file_path, options)?;
+                std::io::copy(&mut File::open(path)?, &mut zip)?;
+            }
+        }
+    } else {
+        let mut archive = ZipArchive::new(File::open(source)?)?;
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i)?;
+            if !file.is_dir() && is_asset(file.name()) {
+                zip.raw_copy_file(file)?;
+            }
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Files that are copied as they are. XML and trail files are written
+/// from the pack instead.
+fn is_asset(file_path: &str) -> bool {
+    !matches!(
+        file_path
+            .rsplit('.')
+            .next()
+            .map(str::to_lowercase)
+            .as_deref(),
+        Some("xml" | "trl")
+    )
+}
+
+/// Returns the trail files referenced by the written `Trail` tags.
+fn write_overlay_data<W: Write>(pack: &MarkerPack, output: W) -> Result<Vec<(String, TrailData)>> {
+    let mut writer = Writer::new_with_indent(output, b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("utf-8"), None)))?;
+    writer.write_event(Event::Start(BytesStart::new("OverlayData")))?;
+
+    let root = pack.root().context("Pack has no root")?;
+    for node in root.children() {
+        write_category(&mut writer, node)?;
+    }
+
+    let mut trails = Vec::new();
+    writer.write_event(Event::Start(BytesStart::new("POIs")))?;
+    for node in root.traverse_pre_order().skip(1) {
+        let marker = node.data();
+        let name = pack.name_of(node.node_id()).to_string();
+
+        for poi in &marker.pois {
+            write_poi(&mut writer, &name, marker, poi)?;
+        }
+
+        for (i, route) in marker.trails.iter().enumerate() {
+            let trail_file = format!("trails/{}_{i}.trl", name.to_lowercase());
+            let mut element = BytesStart::new("Trail");
+            element.push_attribute(("type", name.as_str()));
+            element.push_attribute(("trailData", trail_file.as_str()));
+            element.push_attribute(("texture", route.texture_file.as_str()));
+            for (key, value) in route.display.attributes(&marker.display) {
+                element.push_attribute((key, value.as_str()));
+            }
+            writer.write_event(Event::Empty(element))?;
+
+            trails.push((
+                trail_file,
+                TrailData {
+                    version: 0,
+                    map_id: route.map_id,
+                    segments: route.segments.clone(),
+                },
+            ));
+        }
+    }
+    writer.write_event(Event::End(BytesEnd::new("POIs")))?;
+
+    writer.write_event(Event::End(BytesEnd::new("OverlayData")))?;
+    Ok(trails)
+}
+
+fn write_category<W: Write>(writer: &mut Writer<W>, node: NodeRef<Marker>) -> Result<()> {
+    let marker = node.data();
+    let parent = node.parent().context("Category has no parent")?.data();
+
+    let mut element = BytesStart::new("MarkerCategory");
+    element.push_attribute(("name", marker.name.as_str()));
+    if !marker.label.is_empty() {
+        element.push_attribute(("DisplayName", marker.label.as_str()));
+    }
+    if matches!(marker.kind, MarkerKind::Separator) {
+        element.push_attribute(("IsSeparator", "1"));
+    }
+    if let Some(tip) = &marker.poi_tip {
+        element.push_attribute(("tip-name", tip.as_str()));
+    }
+    if let Some(description) = &marker.poi_description {
+        element.push_attribute(("tip-description", description.as_str()));
+    }
+    if let Some(texture) = marker
+        .texture
+        .as_ref()
+        .filter(|_| marker.texture != parent.texture)
+    {
+        element.push_attribute(("texture", texture.as_str()));
+    }
+    push_inherited(
+        &mut element,
+        marker.icon_file.as_ref(),
+        marker.behavior,
+        &marker.display,
+        &marker.filters,
+        parent,
+    );
+
+    if node.first_child().is_some() {
+        writer.write_event(Event::Start(element))?;
+        for child in node.children() {
+            write_category(writer, child)?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("MarkerCategory")))?;
+    } else {
+        writer.write_event(Event::Empty(element))?;
+    }
+    Ok(())
+}
+
+fn write_poi<W: Write>(
+    writer: &mut Writer<W>,
+    name: &str,
+    marker: &Marker,
+    poi: &Poi,
+) -> Result<()> {
+    let mut element = BytesStart::new("POI");
+    element.push_attribute(("type", name));
+    if let Some(map_id) = poi.map_id {
+        element.push_attribute(("MapID", map_id.to_string().as_str()));
+    }
+    if let Some(position) = poi.position {
+        element.push_attribute(("xpos", position.x.to_string().as_str()));
+        element.push_attribute(("ypos", position.y.to_string().as_str()));
+        element.push_attribute(("zpos", position.z.to_string().as_str()));
+    }
+    element.push_attribute(("GUID", poi.guid.to_string().as_str()));
+    push_inherited(
+        &mut element,
+        poi.icon_file.as_ref(),
+        poi.behavior,
+        &poi.display,
+        &poi.filters,
+        marker,
+    );
+    writer.write_event(Event::Empty(element))?;
+    Ok(())
+}
+
+/// Push the attributes that are inherited from `parent`, but only those
+/// that differ from it.
+fn push_inherited(
+    element: &mut BytesStart,
+    icon_file: Option<&Utf8PathBuf<Utf8UnixEncoding>>,
+    behavior: Option<Behavior>,
+    display: &DisplayAttributes,
+    filters: &Filters,
+    parent: &Marker,
+) {
+    if let Some(icon_file) = icon_file.filter(|_| icon_file != parent.icon_file.as_ref()) {
+        element.push_attribute(("iconFile", icon_file.as_str()));
+    }
+    if let Some(behavior) = behavior.filter(|_| behavior != parent.behavior) {
+        element.push_attribute(("behavior", behavior.id().to_string().as_str()));
+        if let Some(reset_length) = behavior.reset_length() {
+            element.push_attribute(("resetLength", reset_length.to_string().as_str()));
+        }
+    }
+    for (key, value) in display
+        .attributes(&parent.display)
+        .into_iter()
+        .chain(filters.attributes(&parent.filters))
+    {
+        element.push_attribute((key, value.as_str()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine as _;
+    use bevy::math::Vec3;
+    use tempfile::tempdir;
+
+    use orrient_link::Profession;
+
+    use super::super::diagnostics::Severity;
+    use super::super::read_marker_pack;
+
+    /// A 1x1 PNG
+    const ICON: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==";
+
+    #[test]
+    fn test_export_taco() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("test");
+        std::fs::create_dir_all(source.join("trails")).unwrap();
+        std::fs::write(
+            source.join("icon.png"),
+            BASE64_STANDARD.decode(ICON).unwrap(),
+        )
+        .unwrap();
+        trail::to_file(
+            source.join("trails").join("a.trl"),
+            &TrailData {
+                version: 0,
+                map_id: 15,
+                segments: vec![
+                    vec![Vec3::new(1.0, 2.0, 3.0), Vec3::new(4.0, 5.0, 6.0)],
+                    vec![Vec3::new(70.0, 80.0, 90.0), Vec3::new(71.0, 81.0, 91.0)],
+                ],
+            },
+        )
+        .unwrap();
+        std::fs::write(
+            source.join("test.xml"),
+            r#"
+<OverlayData>
+  <MarkerCategory name="A" DisplayName="Item &amp; A" iconFile="icon.png" behavior="4" resetLength="60" alpha="0.5">
+    <MarkerCategory name="B" DisplayName="Item A.B" color="80ff0000" mount="skyscale,griffon" />
+    <MarkerCategory name="S" IsSeparator="1" />
+  </MarkerCategory>
+  <POIs>
+    <POI MapID="15" xpos="100.5" ypos="-100" zpos="3" type="A.B" GUID="AAECAwQFBgcICQoLDA0ODw==" behavior="2" iconSize="2" profession="thief" />
+    <Trail type="A" trailData="trails/a.trl" texture="icon.png" animSpeed="0.5" isWall="1" />
+  </POIs>
+</OverlayData>
+"#,
+        )
+        .unwrap();
+
+        let pack = read_marker_pack(&source).unwrap();
+        assert!(pack.diagnostics().is_empty());
+
+        let exported = dir.path().join("exported.taco");
+        write_taco(&pack, &source, &exported).unwrap();
+        let exported = read_marker_pack(&exported).unwrap();
+        assert!(exported
+            .diagnostics()
+            .with_severity(Severity::Error)
+            .next()
+            .is_none());
+        assert!(exported.image_files.contains_key("icon.png"));
+
+        let node_id = exported.find_by_name(["A"].into_iter()).unwrap();
+        let a = exported.get(node_id).unwrap().data();
+        assert_eq!(a.label, "Item & A");
+        assert_eq!(a.behavior, Some(Behavior::ReappearAfterTime(60.0)));
+        assert_eq!(a.display.alpha, Some(0.5));
+        assert_eq!(a.trails.len(), 1);
+        assert_eq!(a.trails[0].texture_file, "icon.png");
+        assert_eq!(a.trails[0].display.anim_speed, Some(0.5));
+        assert_eq!(a.trails[0].display.is_wall, Some(true));
+        assert_eq!(a.trails[0].display.alpha, Some(0.5));
+        assert_eq!(
+            a.trails[0].segments,
+            vec![
+                vec![Vec3::new(1.0, 2.0, 3.0), Vec3::new(4.0, 5.0, 6.0)],
+                vec![Vec3::new(70.0, 80.0, 90.0), Vec3::new(71.0, 81.0, 91.0)],
+            ]
+        );
+
+        let node_id = exported.find_by_name(["A", "S"].into_iter()).unwrap();
+        let s = exported.get(node_id).unwrap().data();
+        assert!(matches!(s.kind, MarkerKind::Separator));
+
+        let node_id = exported.find_by_name(["A", "B"].into_iter()).unwrap();
+        let b = exported.get(node_id).unwrap().data();
+        let original = pack
+            .get(pack.find_by_name(["A", "B"].into_iter()).unwrap())
+            .unwrap()
+            .data();
+        assert_eq!(b.display, original.display);
+        assert_eq!(b.filters, original.filters);
+        assert_eq!(b.icon_file, original.icon_file);
+
+        assert_eq!(b.pois.len(), 1);
+        let poi = &b.pois[0];
+        let original = &original.pois[0];
+        assert_eq!(poi.guid, original.guid);
+        assert_eq!(poi.map_id, Some(15));
+        assert_eq!(poi.position, Some(Vec3::new(100.5, -100.0, 3.0)));
+        assert_eq!(poi.behavior, Some(Behavior::ReappearDaily));
+        assert_eq!(poi.display, original.display);
+        assert_eq!(poi.display.icon_size, Some(2.0));
+        assert_eq!(poi.filters, original.filters);
+        assert_eq!(poi.filters.professions, Some(vec![Profession::Thief]));
+    }
+
+    #[test]
+    fn test_export_escaped_attributes() {
+        let dir = tempdir().unwrap();
+        let mut xml = r#"
+<OverlayData>
+  <MarkerCategory name="A" DisplayName="&quot;A&quot; &amp; &lt;B&gt;" tip-name="Press &lt;F&gt; &amp; run" tip-description="&apos;Hold&apos; &amp; jump" />
+</OverlayData>
+"#
+        .as_bytes()
+        .to_vec();
+
+        // Every round trip must read back the same values, without
+        // escaping them again.
+        for i in 0..2 {
+            let source = dir.path().join(i.to_string());
+            std::fs::create_dir_all(&source).unwrap();
+            std::fs::write(source.join("test.xml"), &xml).unwrap();
+
+            let pack = read_marker_pack(&source).unwrap();
+            let node_id = pack.find_by_name(["A"].into_iter()).unwrap();
+            let a = pack.get(node_id).unwrap().data();
+            assert_eq!(a.label, r#""A" & <B>"#);
+            assert_eq!(a.poi_tip.as_deref(), Some("Press <F> & run"));
+            assert_eq!(a.poi_description.as_deref(), Some("'Hold' & jump"));
+
+            xml.clear();
+            write_xml(&pack, &mut xml).unwrap();
+        }
+    }
+}
@@ -0,0 +1,480 @@
+//! Blish HUD filter attributes.
+//!
+//! Categories and POIs can limit when they're shown with attributes
+//! like `profession="guardian,warrior"` or `mount="skyscale"`. Each one
+//! is a comma separated list, and a POI is only shown while the player
+//! matches a value in every list that's set.
+
+use bevy::prelude::*;
+use orrient_link::GW2Context;
+use orrient_link::IdentityDef;
+use orrient_link::Profession;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::diagnostics::DiagnosticKind;
+
+/// A value in a filter attribute's list.
+trait FilterValue: Sized + Copy + PartialEq + 'static {
+    /// Every name the value can be written as, in lowercase. The first
+    /// name of a value is used when writing it back out.
+    const NAMES: &'static [(&'static str, Self)];
+
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.to_lowercase();
+        Self::NAMES
+            .iter()
+            .find(|(name, _)| *name == value)
+            .map(|(_, filter)| *filter)
+    }
+
+    fn name(&self) -> String {
+        Self::NAMES
+            .iter()
+            .find(|(_, filter)| filter == self)
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Festival {
+    Halloween,
+    Wintersday,
+    SuperAdventureBox,
+    LunarNewYear,
+    FourWinds,
+    DragonBash,
+}
+
+impl FilterValue for Festival {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("halloween", Festival::Halloween),
+        ("wintersday", Festival::Wintersday),
+        ("superadventurefestival", Festival::SuperAdventureBox),
+        ("superadventurebox", Festival::SuperAdventureBox),
+        ("lunarnewyear", Festival::LunarNewYear),
+        ("festivalofthefourwinds", Festival::FourWinds),
+        ("dragonbash", Festival::DragonBash),
+    ];
+}
+
+/// Mounts, numbered like MumbleLink's `mount_index`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Mount {
+    Jackal = 1,
+    Griffon = 2,
+    Springer = 3,
+    Skimmer = 4,
+    Raptor = 5,
+    RollerBeetle = 6,
+    Warclaw = 7,
+    Skyscale = 8,
+    Skiff = 9,
+    SiegeTurtle = 10,
+}
+
+impl Mount {
+    /// The mount for MumbleLink's `mount_index`. `None` while on foot.
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, mount)| *mount as u8 == index)
+            .map(|(_, mount)| *mount)
+    }
+}
+
+impl FilterValue for Mount {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("jackal", Mount::Jackal),
+        ("griffon", Mount::Griffon),
+        ("springer", Mount::Springer),
+        ("skimmer", Mount::Skimmer),
+        ("raptor", Mount::Raptor),
+        ("rollerbeetle", Mount::RollerBeetle),
+        ("warclaw", Mount::Warclaw),
+        ("skyscale", Mount::Skyscale),
+        ("skiff", Mount::Skiff),
+        ("siegeturtle", Mount::SiegeTurtle),
+    ];
+}
+
+impl FilterValue for Profession {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("guardian", Profession::Guardian),
+        ("warrior", Profession::Warrior),
+        ("engineer", Profession::Engineer),
+        ("ranger", Profession::Ranger),
+        ("thief", Profession::Thief),
+        ("elementalist", Profession::Elementalist),
+        ("mesmer", Profession::Mesmer),
+        ("necromancer", Profession::Necromancer),
+        ("revenant", Profession::Revenant),
+    ];
+}
+
+/// Races, numbered like MumbleLink's `race`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Race {
+    Asura = 0,
+    Charr = 1,
+    Human = 2,
+    Norn = 3,
+    Sylvari = 4,
+}
+
+impl Race {
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, race)| *race as u8 == index)
+            .map(|(_, race)| *race)
+    }
+}
+
+impl FilterValue for Race {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("asura", Race::Asura),
+        ("charr", Race::Charr),
+        ("human", Race::Human),
+        ("norn", Race::Norn),
+        ("sylvari", Race::Sylvari),
+    ];
+}
+
+/// A specialization id from the GW2 API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Specialization(pub u8);
+
+impl FilterValue for Specialization {
+    const NAMES: &'static [(&'static str, Self)] = &[];
+
+    fn parse(value: &str) -> Option<Self> {
+        value.parse().ok().map(Specialization)
+    }
+
+    fn name(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// MumbleLink's `map_type`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MapType(pub u32);
+
+impl FilterValue for MapType {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("redirect", MapType(0)),
+        ("charactercreate", MapType(1)),
+        ("pvp", MapType(2)),
+        ("gvg", MapType(3)),
+        ("instance", MapType(4)),
+        ("public", MapType(5)),
+        ("tournament", MapType(6)),
+        ("tutorial", MapType(7)),
+        ("usertournament", MapType(8)),
+        ("center", MapType(9)),
+        ("eternalbattlegrounds", MapType(10)),
+        ("bluehome", MapType(11)),
+        ("greenhome", MapType(12)),
+        ("redhome", MapType(13)),
+        ("fortunesvale", MapType(14)),
+        ("obsidiansanctum", MapType(15)),
+        ("edgeofthemists", MapType(16)),
+        ("publicmini", MapType(17)),
+        ("bigbattle", MapType(18)),
+        ("wvwlounge", MapType(19)),
+    ];
+
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.to_lowercase();
+        value.parse().ok().map(MapType).or_else(|| {
+            Self::NAMES
+                .iter()
+                .find(|(name, _)| *name == value)
+                .map(|(_, map_type)| *map_type)
+        })
+    }
+
+    fn name(&self) -> String {
+        Self::NAMES
+            .iter()
+            .find(|(_, map_type)| map_type == self)
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_else(|| self.0.to_string())
+    }
+}
+
+/// The filter attributes of a `MarkerCategory` or `POI` tag.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Filters {
+    // festival
+    pub festivals: Option<Vec<Festival>>,
+    // mount
+    pub mounts: Option<Vec<Mount>>,
+    // profession
+    pub professions: Option<Vec<Profession>>,
+    // specialization
+    pub specializations: Option<Vec<Specialization>>,
+    // race
+    pub races: Option<Vec<Race>>,
+    // mapType
+    pub map_types: Option<Vec<MapType>>,
+    // achievementId
+    pub achievement_id: Option<u32>,
+    // achievementBit
+    pub achievement_bit: Option<u32>,
+}
+
+impl Filters {
+    /// Try to parse `key` as a filter attribute. Returns false if the
+    /// key is not a filter attribute.
+    pub(super) fn parse(
+        &mut self,
+        key: &str,
+        value: &str,
+        issues: &mut Vec<DiagnosticKind>,
+    ) -> bool {
+        match key {
+            "festival" => self.festivals = parse_list(key, value, issues),
+            "mount" => self.mounts = parse_list(key, value, issues),
+            "profession" => self.professions = parse_list(key, value, issues),
+            "specialization" => self.specializations = parse_list(key, value, issues),
+            "race" => self.races = parse_list(key, value, issues),
+            "maptype" => self.map_types = parse_list(key, value, issues),
+            "achievementid" => self.achievement_id = value.parse().ok(),
+            "achievementbit" => self.achievement_bit = value.parse().ok(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The attributes that are set and differ from `parent`, as XML
+    /// key/value pairs.
+    pub(super) fn attributes(&self, parent: &Self) -> Vec<(&'static str, String)> {
+        fn list<T: FilterValue>(
+            values: &Option<Vec<T>>,
+            parent: &Option<Vec<T>>,
+        ) -> Option<String> {
+            values
+                .as_ref()
+                .filter(|_| values != parent)
+                .map(|values| values.iter().map(T::name).collect::<Vec<_>>().join(","))
+        }
+
+        let mut attributes = Vec::new();
+        let mut push = |key, value: Option<String>| {
+            if let Some(value) = value {
+                attributes.push((key, value));
+            }
+        };
+        push("festival", list(&self.festivals, &parent.festivals));
+        push("mount", list(&self.mounts, &parent.mounts));
+        push("profession", list(&self.professions, &parent.professions));
+        push(
+            "specialization",
+            list(&self.specializations, &parent.specializations),
+        );
+        push("race", list(&self.races, &parent.races));
+        push("mapType", list(&self.map_types, &parent.map_types));
+        push(
+            "achievementId",
+            self.achievement_id
+                .filter(|_| self.achievement_id != parent.achievement_id)
+                .map(|id| id.to_string()),
+        );
+        push(
+            "achievementBit",
+            self.achievement_bit
+                .filter(|_| self.achievement_bit != parent.achievement_bit)
+                .map(|bit| bit.to_string()),
+        );
+        attributes
+    }
+
+    /// Fill in every filter that isn't set with the one from `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            festivals: self.festivals.or(other.festivals),
+            mounts: self.mounts.or(other.mounts),
+            professions: self.professions.or(other.professions),
+            specializations: self.specializations.or(other.specializations),
+            races: self.races.or(other.races),
+            map_types: self.map_types.or(other.map_types),
+            achievement_id: self.achievement_id.or(other.achievement_id),
+            achievement_bit: self.achievement_bit.or(other.achievement_bit),
+        }
+    }
+
+    /// Returns true if the player in `context` passes every filter.
+    ///
+    /// MumbleLink doesn't tell us which festivals are running or which
+    /// achievements are done, so `festival` and `achievementId` are
+    /// kept for export but don't hide anything.
+    pub fn matches(&self, context: &FilterContext) -> bool {
+        fn allows<T: PartialEq>(values: &Option<Vec<T>>, value: Option<T>) -> bool {
+            match values.as_ref().zip(value) {
+                Some((values, value)) => values.contains(&value),
+                None => true,
+            }
+        }
+
+        // Being on foot is known, so it never matches a mount filter.
+        let mounted = match (&self.mounts, context.mount) {
+            (Some(mounts), Some(mount)) => mount.is_some_and(|mount| mounts.contains(&mount)),
+            _ => true,
+        };
+
+        mounted
+            && allows(&self.professions, context.profession)
+            && allows(&self.specializations, context.specialization)
+            && allows(&self.races, context.race)
+            && allows(&self.map_types, context.map_type)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Parse a comma separated list. An unknown value is reported and left
+/// out, and a list without any known values doesn't filter anything.
+fn parse_list<T: FilterValue>(
+    key: &str,
+    value: &str,
+    issues: &mut Vec<DiagnosticKind>,
+) -> Option<Vec<T>> {
+    let mut values = Vec::new();
+    for part in value
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        match T::parse(part) {
+            Some(value) => values.push(value),
+            None => issues.push(DiagnosticKind::UnknownFilter {
+                key: key.to_string(),
+                value: part.to_string(),
+            }),
+        }
+    }
+    (!values.is_empty()).then_some(values)
+}
+
+/// The state of the player that [`Filters`] are matched against. Unknown
+/// values, like before MumbleLink has connected, pass every filter.
+#[derive(Resource, Clone, Default, Debug, PartialEq)]
+pub struct FilterContext {
+    pub profession: Option<Profession>,
+    pub specialization: Option<Specialization>,
+    pub race: Option<Race>,
+    /// `Some(None)` while on foot.
+    pub mount: Option<Option<Mount>>,
+    pub map_type: Option<MapType>,
+}
+
+impl FilterContext {
+    pub fn new(identity: &IdentityDef, context: &GW2Context) -> Self {
+        Self {
+            profession: match identity.profession {
+                Profession::Unknown => None,
+                profession => Some(profession),
+            },
+            specialization: Some(Specialization(identity.spec)).filter(|spec| spec.0 != 0),
+            race: Race::from_index(identity.race),
+            mount: Some(Mount::from_index(context.mount_index)),
+            map_type: Some(MapType(context.map_type)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_filters(attributes: &[(&str, &str)]) -> (Filters, Vec<DiagnosticKind>) {
+        let mut filters = Filters::default();
+        let mut issues = vec![];
+        for (key, value) in attributes {
+            assert!(filters.parse(key, value, &mut issues));
+        }
+        (filters, issues)
+    }
+
+    #[test]
+    fn test_parse_filters() {
+        let (filters, issues) = parse_filters(&[
+            ("profession", "Guardian, necromancer"),
+            ("mount", "Skyscale,hoverboard"),
+            ("specialization", "27"),
+            ("race", "Charr"),
+            ("maptype", "Public,4"),
+            ("festival", "SuperAdventureBox"),
+            ("achievementid", "1234"),
+        ]);
+        assert_eq!(
+            filters.professions,
+            Some(vec![Profession::Guardian, Profession::Necromancer])
+        );
+        assert_eq!(filters.mounts, Some(vec![Mount::Skyscale]));
+        assert_eq!(filters.specializations, Some(vec![Specialization(27)]));
+        assert_eq!(filters.races, Some(vec![Race::Charr]));
+        assert_eq!(filters.map_types, Some(vec![MapType(5), MapType(4)]));
+        assert_eq!(filters.festivals, Some(vec![Festival::SuperAdventureBox]));
+        assert_eq!(filters.achievement_id, Some(1234));
+        assert_eq!(
+            issues,
+            vec![DiagnosticKind::UnknownFilter {
+                key: "mount".into(),
+                value: "hoverboard".into()
+            }]
+        );
+
+        let (filters, _) = parse_filters(&[("profession", "Commander")]);
+        assert!(filters.is_empty());
+        assert!(!Filters::default().parse("iconsize", "1", &mut vec![]));
+    }
+
+    #[test]
+    fn test_matches() {
+        let (filters, _) =
+            parse_filters(&[("profession", "guardian"), ("mount", "raptor,skimmer")]);
+        let context = FilterContext {
+            profession: Some(Profession::Guardian),
+            mount: Some(Some(Mount::Raptor)),
+            ..Default::default()
+        };
+        assert!(filters.matches(&context));
+        assert!(!filters.matches(&FilterContext {
+            profession: Some(Profession::Thief),
+            ..context.clone()
+        }));
+        assert!(!filters.matches(&FilterContext {
+            mount: Some(None),
+            ..context.clone()
+        }));
+        assert!(filters.matches(&FilterContext {
+            mount: None,
+            ..context.clone()
+        }));
+
+        // Unknown values pass, like before MumbleLink connects.
+        let (filters, _) = parse_filters(&[("race", "norn")]);
+        assert!(filters.matches(&FilterContext::default()));
+        assert!(!filters.matches(&FilterContext {
+            race: Some(Race::Asura),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_write_filters() {
+        let (parent, _) = parse_filters(&[("profession", "guardian"), ("maptype", "public")]);
+        let (child, _) = parse_filters(&[("race", "sylvari"), ("maptype", "3")]);
+        let child = child.or(parent.clone());
+        assert_eq!(
+            child.attributes(&parent),
+            vec![("race", "sylvari".into()), ("mapType", "gvg".into())]
+        );
+    }
+}
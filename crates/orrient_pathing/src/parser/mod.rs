@@ -1,15 +1,23 @@
+mod cache;
+pub mod diagnostics;
+pub mod export;
+pub mod filter;
 pub(crate) mod model;
 pub mod pack;
-mod trail;
+pub mod textures;
+pub mod trail;
 
+use diagnostics::DiagnosticKind;
+use diagnostics::Location;
 use model::MarkerXml;
 use orrient_core::prelude::AppState;
 
 use pack::FullMarkerId;
-use pack::MarkerId;
 use pack::MarkerName;
 use pack::MarkerPack;
 use pack::MarkerPackBuilder;
+use pack::MergedCategory;
+use pack::Route;
 
 use bevy::prelude::*;
 
@@ -18,22 +26,30 @@ use bevy::log::warn;
 use bevy::render::render_asset::RenderAssetUsages;
 use bevy::render::texture::CompressedImageFormats;
 use bevy::render::texture::ImageAddressMode;
+use bevy::render::texture::ImageFormat;
 use bevy::render::texture::ImageSampler;
 use bevy::render::texture::ImageSamplerDescriptor;
 use bevy::render::texture::ImageType;
+use bevy::tasks::block_on;
+use bevy::tasks::futures_lite::future;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::tasks::Task;
 use bevy::utils::HashMap;
 
 use anyhow::Context;
 use anyhow::Result;
 use quick_xml::events::BytesStart;
 use quick_xml::events::Event;
+use quick_xml::name::QName;
 use quick_xml::Reader;
 use serde::Deserialize;
 use serde::Serialize;
+use slab_tree::NodeId;
 use std::borrow::Cow;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::Cursor;
 use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
@@ -43,19 +59,120 @@ use crate::events::ReloadMarkersEvent;
 #[derive(Resource, Deref)]
 struct ConfigDir(PathBuf);
 
-fn load_system(
+/// Where parsed marker packs are cached between launches.
+#[derive(Resource, Deref)]
+struct CacheDir(PathBuf);
+
+/// Packs that are currently being parsed on the [`AsyncComputeTaskPool`].
+#[derive(Resource, Default)]
+struct LoadingPacks {
+    tasks: Vec<(PackId, Task<Result<MarkerPack>>)>,
+    packs: HashMap<PackId, MarkerPack>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PackLoadStatus {
+    Loading,
+    Loaded,
+    Failed(String),
+}
+
+impl PackLoadStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, PackLoadStatus::Loading)
+    }
+}
+
+/// Status of every marker pack in the current (or last) load.
+#[derive(Resource, Clone, Default, Debug, Deref)]
+pub struct PackLoadProgress(HashMap<PackId, PackLoadStatus>);
+
+impl PackLoadProgress {
+    pub fn total(&self) -> usize {
+        self.len()
+    }
+
+    pub fn finished(&self) -> usize {
+        self.values().filter(|status| status.is_finished()).count()
+    }
+
+    /// Fraction of packs that have finished loading, from `0.0` to `1.0`.
+    pub fn fraction(&self) -> f32 {
+        if self.is_empty() {
+            1.0
+        } else {
+            self.finished() as f32 / self.total() as f32
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.values().all(PackLoadStatus::is_finished)
+    }
+}
+
+fn load_system(mut commands: Commands, config_dir: Res<ConfigDir>, cache_dir: Res<CacheDir>) {
+    info!("Loading marker packs...");
+    // Carry on with no packs so the app still leaves the loading state.
+    let paths = find_packs(config_dir.as_path()).unwrap_or_else(|err| {
+        warn!("Error loading marker packs {err:?}");
+        vec![]
+    });
+
+    let task_pool = AsyncComputeTaskPool::get();
+    let mut progress = PackLoadProgress::default();
+    let tasks = paths
+        .into_iter()
+        .map(|(pack_id, path)| {
+            progress.0.insert(pack_id.clone(), PackLoadStatus::Loading);
+            let cache_dir = cache_dir.to_path_buf();
+            let task = task_pool.spawn(async move { load_marker_pack(&path, &cache_dir) });
+            (pack_id, task)
+        })
+        .collect();
+
+    commands.insert_resource(LoadingPacks {
+        tasks,
+        packs: Default::default(),
+    });
+    commands.insert_resource(progress);
+}
+
+fn poll_system(
     mut commands: Commands,
-    config_dir: Res<ConfigDir>,
-    mut images: ResMut<Assets<Image>>,
+    mut loading: ResMut<LoadingPacks>,
+    mut progress: ResMut<PackLoadProgress>,
 ) {
-    info!("Loading marker packs...");
-    match load(config_dir.as_path(), &mut images) {
-        Ok(packs) => {
-            commands.insert_resource(MarkerPacks(packs));
-        }
-        Err(err) => {
-            warn!("Error loading marker packs {err:?}");
-        }
+    let LoadingPacks { tasks, packs } = &mut *loading;
+    tasks.retain_mut(|(pack_id, task)| {
+        let Some(result) = block_on(future::poll_once(task)) else {
+            return true;
+        };
+        let status = match result {
+            Ok(pack) => {
+                let diagnostics = pack.diagnostics();
+                if !diagnostics.is_empty() {
+                    warn!(
+                        "Found {} error(s) and {} warning(s) in {pack_id}",
+                        diagnostics.errors().count(),
+                        diagnostics.warnings().count()
+                    );
+                }
+                packs.insert(pack_id.clone(), pack);
+                PackLoadStatus::Loaded
+            }
+            Err(err) => {
+                warn!("Error when reading marker pack {pack_id}: {err:?}");
+                PackLoadStatus::Failed(err.to_string())
+            }
+        };
+        progress.0.insert(pack_id.clone(), status);
+        false
+    });
+
+    if tasks.is_empty() {
+        info!("Finished loading {} pack(s)", packs.len());
+        commands.insert_resource(MarkerPacks::new(std::mem::take(packs)));
+        commands.remove_resource::<LoadingPacks>();
     }
 }
 
@@ -63,18 +180,16 @@ fn finish_system(mut next_state: ResMut<NextState<AppState>>) {
     next_state.set(AppState::WaitingForMumbleLink);
 }
 
-fn load(path: &Path, images: &mut Assets<Image>) -> Result<HashMap<PackId, MarkerPack>> {
-    let mut packs: HashMap<PackId, MarkerPack> = Default::default();
+/// Find every marker pack in `path`, creating the directory if needed.
+fn find_packs(path: &Path) -> Result<Vec<(PackId, PathBuf)>> {
+    let mut packs = Vec::new();
 
     let iter = std::fs::read_dir(path).or_else(|_| {
         std::fs::create_dir_all(path)?;
         std::fs::read_dir(path)
     })?;
 
-    for path in iter
-        .filter_map(|file| file.ok().map(|file| file.path()))
-        .filter(|file| file.is_file())
-    {
+    for path in iter.filter_map(|file| file.ok().map(|file| file.path())) {
         let Some(filename) = path
             .file_name()
             .map(|filename| filename.to_string_lossy().to_string())
@@ -82,28 +197,23 @@ fn load(path: &Path, images: &mut Assets<Image>) -> Result<HashMap<PackId, Marke
             continue;
         };
 
-        let Some(extension) = path.extension().map(|ext| ext.to_string_lossy()) else {
+        if !path.is_dir() && !is_pack_archive(&path) {
+            warn!("Unknown file extension: {:?}", path);
             continue;
-        };
-
-        match extension {
-            Cow::Borrowed("taco") | Cow::Borrowed("zip") => match read_marker_pack(&path, images) {
-                Ok(pack) => {
-                    packs.insert(PackId(filename), pack);
-                }
-                Err(err) => {
-                    warn!("Error when reading marker pack {err:?}");
-                }
-            },
-            _ => {
-                warn!("Unknown file extension: {:?}", path);
-            }
         }
+
+        packs.push((PackId(filename), path));
     }
-    info!("Finished loading {} pack(s)", packs.len());
     Ok(packs)
 }
 
+fn is_pack_archive(path: &Path) -> bool {
+    matches!(
+        path.extension().map(|ext| ext.to_string_lossy()),
+        Some(Cow::Borrowed("taco") | Cow::Borrowed("zip"))
+    )
+}
+
 #[derive(Hash, Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PackId(pub String);
 
@@ -126,6 +236,93 @@ impl std::fmt::Display for PackId {
 pub struct MarkerPacks(HashMap<PackId, MarkerPack>);
 
 impl MarkerPacks {
+    /// Attach each pack's orphaned POIs and trails to the category of the
+    /// same name in another pack. When several packs define it, the
+    /// first by [`PackId`] gets them.
+    pub(crate) fn new(mut packs: HashMap<PackId, MarkerPack>) -> Self {
+        let mut pack_ids = packs.keys().cloned().collect::<Vec<_>>();
+        pack_ids.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut unresolved = 0;
+        for pack_id in &pack_ids {
+            let orphans = std::mem::take(&mut packs.get_mut(pack_id).unwrap().orphans);
+
+            for (marker_name, mut poi) in orphans.pois {
+                let Some((owner, node_id)) = Self::find_marker(&packs, &pack_ids, &marker_name)
+                else {
+                    unresolved += 1;
+                    continue;
+                };
+                let mut node = packs.get_mut(&owner).unwrap().get_mut(node_id).unwrap();
+                let marker = node.data();
+                if let Some(map_id) = poi.map_id {
+                    marker.map_ids.insert(map_id);
+                }
+                poi.inherit(marker);
+                poi.from_pack = Some(pack_id.clone());
+                marker.pois.push(poi);
+            }
+
+            for (marker_name, trail) in orphans.trails {
+                let Some((owner, node_id)) = Self::find_marker(&packs, &pack_ids, &marker_name)
+                else {
+                    unresolved += 1;
+                    continue;
+                };
+                let mut node = packs.get_mut(&owner).unwrap().get_mut(node_id).unwrap();
+                let marker = node.data();
+                let Some(texture_file) = trail.texture_file.or_else(|| marker.texture.clone())
+                else {
+                    unresolved += 1;
+                    continue;
+                };
+                marker.map_ids.insert(trail.map_id);
+                marker.trails.push(Route {
+                    map_id: trail.map_id,
+                    segments: trail.segments,
+                    texture_file,
+                    display: trail.display.or(marker.display),
+                    from_pack: Some(pack_id.clone()),
+                });
+            }
+        }
+        if unresolved > 0 {
+            warn!("{unresolved} POI(s) and trail(s) have no category in any pack");
+        }
+
+        Self(packs)
+    }
+
+    /// The first pack in `pack_ids` that defines `marker_name`.
+    fn find_marker(
+        packs: &HashMap<PackId, MarkerPack>,
+        pack_ids: &[PackId],
+        marker_name: &MarkerName,
+    ) -> Option<(PackId, NodeId)> {
+        pack_ids.iter().find_map(|pack_id| {
+            packs[pack_id]
+                .find_by_name(marker_name.clone())
+                .map(|node_id| (pack_id.clone(), node_id))
+        })
+    }
+
+    /// The category `marker_name` merged across every pack that defines
+    /// it, including what other packs added to it.
+    pub fn merged(&self, marker_name: &MarkerName) -> MergedCategory<'_> {
+        let mut markers = self
+            .iter()
+            .filter_map(|(pack_id, pack)| {
+                let node_id = pack.find_by_name(marker_name.clone())?;
+                Some((pack_id, pack.get(node_id)?.data()))
+            })
+            .collect::<Vec<_>>();
+        markers.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        MergedCategory {
+            name: marker_name.clone(),
+            markers,
+        }
+    }
+
     pub fn get_map_markers<'a>(
         &'a self,
         map_id: &'a u32,
@@ -150,45 +347,133 @@ enum Tag {
     POIs,
     Poi(model::PoiXml),
     Trail(model::TrailXml),
+    Route(model::RouteXml),
     UnknownField(String),
 }
 
 impl Tag {
-    fn from_element(element: &BytesStart) -> Result<Tag> {
+    fn from_element(element: &BytesStart, issues: &mut Vec<DiagnosticKind>) -> Result<Tag> {
         let tag = core::str::from_utf8(element.name().0)?;
         Ok(match tag.to_lowercase().as_ref() {
             "overlaydata" => Tag::OverlayData,
-            "markercategory" => Tag::Marker(MarkerXml::from_attrs(element.attributes())?),
+            "markercategory" => Tag::Marker(MarkerXml::from_attrs(element.attributes(), issues)?),
             "pois" => Tag::POIs,
-            "poi" => Tag::Poi(model::PoiXml::from_attrs(element.attributes())?),
-            "trail" => Tag::Trail(model::TrailXml::from_attrs(element.attributes())?),
+            "poi" => Tag::Poi(model::PoiXml::from_attrs(element.attributes(), issues)?),
+            "trail" => Tag::Trail(model::TrailXml::from_attrs(element.attributes(), issues)?),
+            "route" => Tag::Route(model::RouteXml::from_attrs(element.attributes(), issues)?),
             field => Tag::UnknownField(field.to_string()),
         })
     }
 
-    fn apply(self, builder: &mut MarkerPackBuilder) {
+    /// Parse `element` and apply it to `builder`, reporting any problems
+    /// at `location`. Returns true when it opened a category, which has
+    /// to be closed again with [`MarkerPackBuilder::up`].
+    fn read(builder: &mut MarkerPackBuilder, element: &BytesStart, location: Location) -> bool {
+        let mut issues = Vec::new();
+        let tag = Tag::from_element(element, &mut issues);
+        for kind in issues {
+            builder.report(&location, kind);
+        }
+        match tag {
+            Ok(tag) => {
+                let is_category = matches!(tag, Tag::Marker(_));
+                tag.apply(builder, location);
+                is_category
+            }
+            Err(err) => {
+                builder.report(
+                    &location,
+                    DiagnosticKind::InvalidTag {
+                        tag: String::from_utf8_lossy(element.name().0).to_string(),
+                        error: err.to_string(),
+                    },
+                );
+                false
+            }
+        }
+    }
+
+    fn apply(self, builder: &mut MarkerPackBuilder, location: Location) {
         match self {
             Tag::OverlayData => {
                 builder.new_root();
             }
-            Tag::Marker(marker) => {
+            Tag::Marker(mut marker) => {
+                marker.icon_file = marker
+                    .icon_file
+                    .map(|path| builder.resolve_file(path.as_str(), &location).into());
+                marker.texture = marker
+                    .texture
+                    .map(|path| builder.resolve_file(&path, &location));
                 builder.add_marker(marker);
             }
             Tag::POIs => {}
-            Tag::Poi(poi) => {
-                builder.add_poi(poi);
+            Tag::Poi(mut poi) => {
+                poi.icon_file = poi
+                    .icon_file
+                    .map(|path| builder.resolve_file(path.as_str(), &location).into());
+                builder.add_poi(poi, location);
+            }
+            Tag::Trail(mut trail) => {
+                if trail.id.is_empty() {
+                    let Some(category) = builder.current_category() else {
+                        builder.report(
+                            &location,
+                            DiagnosticKind::InvalidTag {
+                                tag: "Trail".into(),
+                                error: "Trail missing field `trail.type`.".into(),
+                            },
+                        );
+                        return;
+                    };
+                    trail.id = category;
+                }
+                trail.trail_file = builder.resolve_path(&location.file, &trail.trail_file);
+                trail.texture_file = trail
+                    .texture_file
+                    .map(|path| builder.resolve_file(&path, &location));
+                builder.add_trail_tag(trail, location);
             }
-            Tag::Trail(trail) => {
-                builder.add_trail_tag(trail);
+            Tag::Route(mut route) => {
+                if route.id.is_none() {
+                    route.id = builder.current_category();
+                }
+                route.texture_file = route
+                    .texture_file
+                    .map(|path| builder.resolve_file(&path, &location));
+                builder.start_route(route, location);
             }
             Tag::UnknownField(element) => {
-                warn!("Unknown Field: {element}");
+                builder.report(&location, DiagnosticKind::UnknownTag(element));
             }
         }
     }
 }
 
-fn read_marker_pack(path: &Path, images: &mut Assets<Image>) -> Result<MarkerPack> {
+/// Load a marker pack from the cache when it's up to date, otherwise
+/// parse it and update the cache.
+fn load_marker_pack(path: &Path, cache_dir: &Path) -> Result<MarkerPack> {
+    let cache_file = cache::cache_file(cache_dir, path)?;
+    let key = cache::CacheKey::new(path)?;
+    match cache::read(&cache_file, &key) {
+        Ok(pack) => {
+            info!("Loaded from cache: {}", pack.id());
+            return Ok(pack);
+        }
+        Err(err) => debug!("Not using cache for {path:?}: {err:?}"),
+    }
+
+    let pack = read_marker_pack(path)?;
+    if let Err(err) = cache::write(&cache_file, &key, &pack) {
+        warn!("Error when caching marker pack {}: {err:?}", pack.id());
+    }
+    Ok(pack)
+}
+
+/// Parse the marker pack archive or unpacked pack directory at `path`.
+/// Problems in the pack are collected in [`MarkerPack::diagnostics`]
+/// rather than failing, so this only errors when `path` can't be read.
+pub fn read_marker_pack(path: &Path) -> Result<MarkerPack> {
     let pack_filename = path
         .file_name()
         .context("Could not determine filename in {path:?}")?
@@ -198,43 +483,17 @@ fn read_marker_pack(path: &Path, images: &mut Assets<Image>) -> Result<MarkerPac
     let mut builder = MarkerPackBuilder::new(pack_filename);
     info!("Parsing: {}", builder.pack.id());
 
-    let pack = File::open(path)?;
-    let mut zip = zip::ZipArchive::new(pack)?;
-    for i in 0..zip.len() {
-        let mut file = zip.by_index(i)?;
-        let file_path = file.name().to_string();
-        let Some(ext) = file_path.rsplit(".").next() else {
-            continue;
-        };
-        match ext {
-            "xml" => {
-                let _ = parse_xml(&mut builder, &file_path, BufReader::new(file));
-            }
-            "png" => {
-                let mut bytes = Vec::new();
-                file.read_to_end(&mut bytes)?;
-                let image: Image = Image::from_buffer(
-                    &bytes,
-                    ImageType::Extension(ext),
-                    CompressedImageFormats::all(),
-                    false,
-                    ImageSampler::Descriptor(ImageSamplerDescriptor {
-                        address_mode_u: ImageAddressMode::Repeat,
-                        address_mode_v: ImageAddressMode::Repeat,
-                        ..default()
-                    }),
-                    RenderAssetUsages::all(), // TODO Maybe only needs to be RENDER_WORLD?
-                )
-                .unwrap();
-                builder.add_image(file_path, image, images);
-            }
-            "trl" => match trail::read(file) {
-                Ok(trail_data) => builder.add_trail_data(file_path, trail_data),
-                Err(err) => {
-                    warn!("Error parsing trail file: {err}: {file_path}")
-                }
-            },
-            ext => debug!("Skipping unknown extension {ext}"),
+    if path.is_dir() {
+        for (file_path, path) in dir_files(path)? {
+            read_file(&mut builder, file_path, File::open(&path)?)?;
+        }
+    } else {
+        let pack = File::open(path)?;
+        let mut zip = zip::ZipArchive::new(pack)?;
+        for i in 0..zip.len() {
+            let file = zip.by_index(i)?;
+            let file_path = file.name().to_string();
+            read_file(&mut builder, file_path, file)?;
         }
     }
     info!("Building: {}", builder.pack.id());
@@ -243,6 +502,128 @@ fn read_marker_pack(path: &Path, images: &mut Assets<Image>) -> Result<MarkerPac
     Ok(marker_pack)
 }
 
+/// Every file in an unpacked marker pack, in a stable order. Paths are
+/// made relative to `root` so they match the paths of a zipped pack.
+fn dir_files(root: &Path) -> Result<Vec<(String, PathBuf)>> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) -> Result<()> {
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        paths.sort();
+
+        for path in paths {
+            if path.is_dir() {
+                walk(root, &path, files)?;
+                continue;
+            }
+
+            let file_path = path
+                .strip_prefix(root)?
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((file_path, path));
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    walk(root, root, &mut files)?;
+    Ok(files)
+}
+
+fn read_file<R: Read>(
+    builder: &mut MarkerPackBuilder,
+    file_path: String,
+    mut file: R,
+) -> Result<()> {
+    builder.add_file(&file_path);
+    let Some(ext) = file_path.rsplit(".").next() else {
+        return Ok(());
+    };
+    match ext.to_lowercase().as_str() {
+        "xml" => {
+            let _ = parse_xml(builder, &file_path, BufReader::new(file));
+        }
+        "trl" => match trail::read(file) {
+            Ok(trail_data) if trail_data.segments.iter().flatten().any(|p| !p.is_finite()) => {
+                builder.report(
+                    &Location::file(file_path),
+                    DiagnosticKind::InvalidTrailData("Coordinates are not finite".into()),
+                )
+            }
+            Ok(trail_data) => builder.add_trail_data(file_path, trail_data),
+            Err(err) => builder.report(
+                &Location::file(file_path),
+                DiagnosticKind::InvalidTrailData(err.to_string()),
+            ),
+        },
+        ext if ImageFormat::from_extension(ext).is_some() => {
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            // Textures are only decoded when they're used.
+            match check_image(&file_path, &data) {
+                Ok(()) => builder.add_image(file_path, data),
+                Err(err) => builder.report(
+                    &Location::file(file_path),
+                    DiagnosticKind::InvalidImage(err.to_string()),
+                ),
+            }
+        }
+        ext => debug!("Skipping unknown extension {ext}"),
+    }
+    Ok(())
+}
+
+/// Check that an image's header matches the extension of `file_path`,
+/// without decoding the whole image.
+fn check_image(file_path: &str, data: &[u8]) -> Result<()> {
+    let ext = file_path.rsplit(".").next().unwrap_or_default();
+    match image::ImageFormat::from_extension(ext) {
+        Some(format) => {
+            image::ImageReader::with_format(Cursor::new(data), format).into_dimensions()?;
+        }
+        // The `image` crate doesn't read DDS, but decoding one is little
+        // more than reading its header anyway.
+        None => {
+            decode_image(file_path, data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Decode an image in any format Bevy supports, going by the extension
+/// of `file_path`.
+fn decode_image(file_path: &str, data: &[u8]) -> Result<Image> {
+    let ext = file_path
+        .rsplit(".")
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let image = Image::from_buffer(
+        // Only used to name the image in DDS errors.
+        #[cfg(debug_assertions)]
+        file_path.to_string(),
+        data,
+        ImageType::Extension(&ext),
+        CompressedImageFormats::all(),
+        false,
+        image_sampler(),
+        RenderAssetUsages::all(), // TODO Maybe only needs to be RENDER_WORLD?
+    )?;
+    Ok(image)
+}
+
+/// Textures are repeated along trails.
+fn image_sampler() -> ImageSampler {
+    ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..default()
+    })
+}
+
 fn parse_xml<R: Read + BufRead>(
     builder: &mut MarkerPackBuilder,
     filename: &str,
@@ -251,50 +632,59 @@ fn parse_xml<R: Read + BufRead>(
     let mut reader = Reader::from_reader(reader);
     reader.config_mut().trim_text(true);
     let mut buf = Vec::new();
+    let mut last_error_position = None;
+    // Whether each open element opened a category.
+    let mut open_tags: Vec<bool> = Vec::new();
 
     loop {
         buf.clear();
+        // With `trim_text` this may point at whitespace in front of the
+        // element, which is close enough to find it.
+        let position = reader.buffer_position() as u64;
         match reader.read_event_into(&mut buf) {
             Ok(event) => match event {
                 Event::Start(element) => {
-                    match Tag::from_element(&element) {
-                        Ok(tag) => tag.apply(builder),
-                        Err(err) => {
-                            warn!(
-                                "Error parsing tag {:?} in file {:?}: {:?}",
-                                &element, filename, err
-                            );
-                            continue;
-                        }
-                    };
+                    open_tags.push(Tag::read(
+                        builder,
+                        &element,
+                        Location::new(filename, position),
+                    ));
                 }
                 Event::Empty(element) => {
-                    match Tag::from_element(&element) {
-                        Ok(tag) => tag.apply(builder),
-                        Err(err) => {
-                            warn!(
-                                "Error parsing tag {:?} in file {:?}: {:?}",
-                                &element, filename, err
-                            );
-                            continue;
-                        }
-                    };
-                    builder.up();
+                    if Tag::read(builder, &element, Location::new(filename, position)) {
+                        builder.up();
+                    }
+                    if is_route(&element.name()) {
+                        builder.end_route();
+                    }
                 }
-                Event::End(_) => {
-                    builder.up();
+                Event::End(element) => {
+                    if open_tags.pop().unwrap_or_default() {
+                        builder.up();
+                    }
+                    if is_route(&element.name()) {
+                        builder.end_route();
+                    }
                 }
                 Event::Eof => break,
                 Event::Decl(_) => {}
                 Event::Comment(_) => {}
                 unknown_event => debug!("unknown_event in {filename}: {unknown_event:?}"),
             },
-            Err(err) => panic!(
-                "Error reading {:?} at position {}: {:?}",
-                filename,
-                reader.buffer_position(),
-                err
-            ),
+            Err(err) => {
+                let error_position = reader.buffer_position() as u64;
+                builder.report(
+                    &Location::new(filename, error_position),
+                    DiagnosticKind::XmlError(err.to_string()),
+                );
+                // The reader skips past most malformed markup, so keep
+                // going. If it fails twice in the same place it's stuck
+                // and the rest of the file is lost.
+                if last_error_position == Some(error_position) {
+                    break;
+                }
+                last_error_position = Some(error_position);
+            }
         }
     }
 
@@ -302,6 +692,10 @@ fn parse_xml<R: Read + BufRead>(
     Ok(())
 }
 
+fn is_route(name: &QName) -> bool {
+    name.as_ref().eq_ignore_ascii_case(b"route")
+}
+
 pub(crate) struct Plugin;
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
@@ -312,12 +706,23 @@ impl bevy::prelude::Plugin for Plugin {
                 .join("markers")
                 .to_path_buf(),
         ));
+        app.insert_resource(CacheDir(
+            dirs::cache_dir().unwrap().join("orrient").join("packs"),
+        ));
 
+        app.add_plugins(textures::Plugin);
+
+        app.add_systems(OnEnter(AppState::ParsingMarkerPacks), load_system);
         app.add_systems(
-            OnEnter(AppState::ParsingMarkerPacks),
-            (load_system, finish_system).chain(),
+            Update,
+            (
+                load_system.run_if(on_event::<ReloadMarkersEvent>()),
+                poll_system.run_if(resource_exists::<LoadingPacks>),
+                finish_system.run_if(
+                    in_state(AppState::ParsingMarkerPacks).and_then(resource_exists::<MarkerPacks>),
+                ),
+            ),
         );
-        app.add_systems(Update, load_system.run_if(on_event::<ReloadMarkersEvent>()));
     }
 }
 
@@ -327,12 +732,22 @@ mod tests {
 
     use std::io::Write;
 
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine as _;
+    use diagnostics::Diagnostic;
     use lazy_static::lazy_static;
     use pack::MarkerPath;
-    use petgraph::prelude::NodeIndex;
     use tempfile::tempdir;
     use zip::{write::SimpleFileOptions, ZipWriter};
 
+    fn load(path: &Path) -> HashMap<PackId, MarkerPack> {
+        find_packs(path)
+            .unwrap()
+            .into_iter()
+            .map(|(pack_id, path)| (pack_id, read_marker_pack(&path).unwrap()))
+            .collect()
+    }
+
     lazy_static! {
         static ref TEST_PACKS: HashMap<PackId, MarkerPack> = {
             let dir = tempdir().unwrap().into_path();
@@ -342,7 +757,7 @@ mod tests {
 
             writer.start_file("test_1.xml", options).unwrap();
             writer
-                .write(
+                .write_all(
                     r#"
 <OverlayData>
   <MarkerCategory name="A" DisplayName="Item A">
@@ -363,7 +778,7 @@ mod tests {
 
             writer.start_file("test_2.xml", options).unwrap();
             writer
-                .write(
+                .write_all(
                     r#"
 <OverlayData>
   <MarkerCategory name="A" DisplayName="Item A">
@@ -383,7 +798,7 @@ mod tests {
 
             writer.start_file("test_3.xml", options).unwrap();
             writer
-                .write(
+                .write_all(
                     r#"
 <OverlayData>
   <MarkerCategory name="G" DisplayName="Item G">
@@ -405,8 +820,7 @@ mod tests {
                 .unwrap();
 
             writer.finish().unwrap();
-            let mut images: Assets<Image> = Assets::default();
-            load(&dir, &mut images).unwrap()
+            load(&dir)
         };
     }
 
@@ -414,13 +828,13 @@ mod tests {
     fn test_iter() {
         let markers = TEST_PACKS.get(&PackId("test.taco".into())).unwrap();
 
-        let root: NodeIndex = *MarkerPath::from_string(&markers.tree, "A")
+        let root: NodeId = *MarkerPath::from_string(&markers.tree, "A")
             .unwrap()
             .last()
             .unwrap();
         let mut iter = markers
-            .recurse(root.into())
-            .map(|(id, _)| markers.name_of(id).0.join("."));
+            .recurse(root)
+            .map(|node| markers.name_of(node.node_id()).0.join("."));
 
         //     A
         //    / \
@@ -440,13 +854,13 @@ mod tests {
         // H   I
         //   / | \
         //  J  K  L
-        let root: NodeIndex = *MarkerPath::from_string(&markers.tree, "G")
+        let root: NodeId = *MarkerPath::from_string(&markers.tree, "G")
             .unwrap()
             .last()
             .unwrap();
         let mut iter = markers
-            .recurse(root.into())
-            .map(|(id, _)| markers.name_of(id).0.join("."));
+            .recurse(root)
+            .map(|node| markers.name_of(node.node_id()).0.join("."));
         assert_eq!(iter.next().unwrap(), "G");
         assert_eq!(iter.next().unwrap(), "G.H");
         assert_eq!(iter.next().unwrap(), "G.I");
@@ -460,13 +874,13 @@ mod tests {
         //   B   E
         //  / \   \
         // C   D   F
-        let root: NodeIndex = *MarkerPath::from_string(&markers.tree, "A.B")
+        let root: NodeId = *MarkerPath::from_string(&markers.tree, "A.B")
             .unwrap()
             .last()
             .unwrap();
         let mut iter = markers
-            .recurse(root.into())
-            .map(|(id, _)| markers.name_of(id).0.join("."));
+            .recurse(root)
+            .map(|node| markers.name_of(node.node_id()).0.join("."));
         assert_eq!(iter.next().unwrap(), "A.B");
         assert_eq!(iter.next().unwrap(), "A.B.C");
         assert_eq!(iter.next().unwrap(), "A.B.D");
@@ -477,13 +891,13 @@ mod tests {
         //   B   E
         //  / \   \
         // C   D   F
-        let root: NodeIndex = *MarkerPath::from_string(&markers.tree, "A.B.C")
+        let root: NodeId = *MarkerPath::from_string(&markers.tree, "A.B.C")
             .unwrap()
             .last()
             .unwrap();
         let mut iter = markers
-            .recurse(root.into())
-            .map(|(id, _)| markers.name_of(id).0.join("."));
+            .recurse(root)
+            .map(|node| markers.name_of(node.node_id()).0.join("."));
         assert_eq!(iter.next().unwrap(), "A.B.C");
         assert!(iter.next().is_none());
     }
@@ -493,22 +907,445 @@ mod tests {
         let pack = TEST_PACKS.get(&PackId("test.taco".into())).unwrap();
         let mut roots = pack.roots();
         let root = roots.next().unwrap();
-        assert_eq!(pack.get_marker(root).unwrap().name, "A");
+        assert_eq!(pack.get(root).unwrap().data().name, "A");
         {
-            let mut iter = pack.iter(root).map(|(_idx, marker)| &marker.name);
+            let mut iter = pack.iter(root).map(|node| node.data().name.clone());
             assert_eq!(iter.next().unwrap(), "B");
             assert_eq!(iter.next().unwrap(), "E");
         }
 
         let root = roots.next().unwrap();
-        assert_eq!(pack.get_marker(root).unwrap().name, "G");
+        assert_eq!(pack.get(root).unwrap().data().name, "G");
         {
-            let mut iter = pack.iter(root).map(|(_idx, marker)| &marker.name);
+            let mut iter = pack.iter(root).map(|node| node.data().name.clone());
             assert_eq!(iter.next().unwrap(), "H");
             assert_eq!(iter.next().unwrap(), "I");
         }
     }
 
+    #[test]
+    fn test_directory_pack() {
+        let dir = tempdir().unwrap().into_path();
+        let pack_dir = dir.join("test");
+        std::fs::create_dir_all(pack_dir.join("data")).unwrap();
+        std::fs::write(
+            pack_dir.join("test.xml"),
+            r#"
+<OverlayData>
+  <MarkerCategory name="A" DisplayName="Item A" />
+</OverlayData>
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            pack_dir.join("data").join("more.xml"),
+            r#"
+<OverlayData>
+  <MarkerCategory name="A" DisplayName="Item A">
+    <MarkerCategory name="B" DisplayName="Item A.B" />
+  </MarkerCategory>
+  <POIs>
+    <POI MapID="15" xpos="100.0" ypos="100.0" zpos="-100.0" type="A.B" />
+  </POIs>
+</OverlayData>
+"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("readme.txt"), "Not a pack").unwrap();
+
+        let packs = load(&dir);
+        assert_eq!(packs.len(), 1);
+
+        let pack = packs.get(&PackId("test".into())).unwrap();
+        let node_id = pack.find_by_name(["A", "B"].into_iter()).unwrap();
+        let marker = pack.get(node_id).unwrap().data();
+        assert_eq!(marker.pois.len(), 1);
+        assert!(marker.map_ids.contains(&15));
+    }
+
+    fn write_taco(path: &Path, files: &[(&str, &str)]) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn xml_errors<'a>(pack: &'a MarkerPack, file: &'a str) -> Vec<&'a Diagnostic> {
+        pack.diagnostics()
+            .in_file(file)
+            .filter(|diagnostic| matches!(diagnostic.kind, DiagnosticKind::XmlError(_)))
+            .collect()
+    }
+
+    #[test]
+    fn test_truncated_xml() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.taco");
+        write_taco(
+            &path,
+            &[
+                (
+                    "a.xml",
+                    r#"
+<OverlayData>
+  <MarkerCategory name="A">
+    <MarkerCategory name="B" />
+  </MarkerCategory>
+  <POIs>
+    <POI MapID="15" xpos="1.0" ypos="1.0" zpos="1.0" type="A.B" />
+    <POI MapID="15" xpos="2.0" ypos="2"#,
+                ),
+                (
+                    "b.xml",
+                    r#"
+<OverlayData>
+  <MarkerCategory name="C" />
+  <POIs>
+    <POI MapID="15" xpos="3.0" ypos="3.0" zpos="3.0" type="C" />
+  </POIs>
+</OverlayData>
+"#,
+                ),
+            ],
+        );
+
+        let pack = read_marker_pack(&path).unwrap();
+
+        // Everything before the truncation is kept.
+        let node_id = pack.find_by_name(["A", "B"].into_iter()).unwrap();
+        assert_eq!(pack.get(node_id).unwrap().data().pois.len(), 1);
+
+        // The rest of the archive is still read.
+        let node_id = pack.find_by_name(["C"].into_iter()).unwrap();
+        assert_eq!(pack.get(node_id).unwrap().data().pois.len(), 1);
+
+        assert!(!xml_errors(&pack, "a.xml").is_empty());
+        assert!(xml_errors(&pack, "b.xml").is_empty());
+        assert!(pack.diagnostics().has_errors());
+    }
+
+    #[test]
+    fn test_mismatched_xml() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.taco");
+        write_taco(
+            &path,
+            &[(
+                "a.xml",
+                r#"
+<OverlayData>
+  <MarkerCategory name="A">
+    <MarkerCategory name="B">
+  </MarkerCategory>
+  <POIs>
+    <POI MapID="15" xpos="1.0" ypos="1.0" zpos="1.0" type="A.B" />
+  </POIs>
+</OverlayData>
+<OverlayData>
+  <MarkerCategory name="C" />
+</Overlay>
+"#,
+            )],
+        );
+
+        let pack = read_marker_pack(&path).unwrap();
+
+        let node_id = pack.find_by_name(["A", "B"].into_iter()).unwrap();
+        assert_eq!(pack.get(node_id).unwrap().data().pois.len(), 1);
+        assert!(pack.find_by_name(["C"].into_iter()).is_some());
+
+        let errors = xml_errors(&pack, "a.xml");
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|error| error.position.is_some()));
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let dir = tempdir().unwrap();
+        let pack_dir = dir.path().join("test");
+        std::fs::create_dir_all(pack_dir.join("Icons")).unwrap();
+        std::fs::write(
+            pack_dir.join("test.xml"),
+            r#"
+<OverlayData>
+  <MarkerCategory name="A" iconFile="icons/a.png">
+    <MarkerCategory name="B" iconFile="missing.png" />
+  </MarkerCategory>
+  <POIs>
+    <Trail type="A" trailData="empty.trl" texture="Icons/A.png" />
+  </POIs>
+</OverlayData>
+"#,
+        )
+        .unwrap();
+        // A 1x1 PNG
+        std::fs::write(
+            pack_dir.join("Icons").join("A.png"),
+            BASE64_STANDARD
+                .decode("iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==")
+                .unwrap(),
+        )
+        .unwrap();
+        // Only the version and map id.
+        std::fs::write(pack_dir.join("empty.trl"), [0, 0, 0, 0, 15, 0, 0, 0]).unwrap();
+
+        let pack = read_marker_pack(&pack_dir).unwrap();
+        let kinds = pack
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| &diagnostic.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                &DiagnosticKind::MissingFile("missing.png".into()),
+                &DiagnosticKind::EmptyTrail {
+                    id: "A".into(),
+                    trail_file: "empty.trl".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_formats() {
+        let dir = tempdir().unwrap();
+        let pack_dir = dir.path().join("test");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+
+        // A 1x1 24-bit BMP
+        let mut bmp = vec![];
+        bmp.extend(b"BM");
+        bmp.extend(58u32.to_le_bytes());
+        bmp.extend(0u32.to_le_bytes());
+        bmp.extend(54u32.to_le_bytes());
+        bmp.extend(40u32.to_le_bytes());
+        bmp.extend(1i32.to_le_bytes());
+        bmp.extend(1i32.to_le_bytes());
+        bmp.extend(1u16.to_le_bytes());
+        bmp.extend(24u16.to_le_bytes());
+        bmp.extend([0u32, 4, 2835, 2835, 0, 0].map(u32::to_le_bytes).concat());
+        bmp.extend([0xff, 0x00, 0x00, 0x00]);
+        std::fs::write(pack_dir.join("Icon.BMP"), bmp).unwrap();
+        std::fs::write(pack_dir.join("broken.png"), "Not a PNG").unwrap();
+
+        let pack = read_marker_pack(&pack_dir).unwrap();
+        assert!(pack.image_files.contains_key("icon.bmp"));
+        assert!(!pack.image_files.contains_key("broken.png"));
+        assert!(matches!(
+            pack.diagnostics()
+                .in_file("broken.png")
+                .map(|diagnostic| &diagnostic.kind)
+                .collect::<Vec<_>>()[..],
+            [DiagnosticKind::InvalidImage(_)]
+        ));
+    }
+
+    #[test]
+    fn test_route() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.taco");
+        write_taco(
+            &path,
+            &[(
+                "a.xml",
+                r#"
+<OverlayData>
+  <MarkerCategory name="A" texture="trail.png" animSpeed="2" />
+  <MarkerCategory name="B" texture="trail.png">
+    <Route MapID="15">
+      <POI xpos="1.0" ypos="1.0" zpos="1.0" type="B" />
+      <POI xpos="2.0" ypos="2.0" zpos="2.0" type="B" />
+    </Route>
+    <MarkerCategory name="C" />
+  </MarkerCategory>
+  <POIs>
+    <Route type="A" BackwardDirection="1" trailScale="3">
+      <POI MapID="15" xpos="1.0" ypos="1.0" zpos="1.0" type="A" />
+      <POI MapID="15" xpos="2.0" ypos="2.0" zpos="2.0" type="A" />
+      <POI MapID="15" xpos="3.0" ypos="3.0" zpos="3.0" type="A" />
+    </Route>
+    <POI MapID="15" xpos="4.0" ypos="4.0" zpos="4.0" type="A" />
+  </POIs>
+</OverlayData>
+"#,
+            )],
+        );
+        let pack = read_marker_pack(&path).unwrap();
+
+        let node_id = pack.find_by_name(["A"].into_iter()).unwrap();
+        let a = pack.get(node_id).unwrap().data();
+        assert_eq!(a.pois.len(), 1);
+        assert_eq!(a.trails.len(), 1);
+        let route = &a.trails[0];
+        assert_eq!(route.map_id, 15);
+        assert_eq!(
+            route.segments,
+            vec![vec![Vec3::splat(3.0), Vec3::splat(2.0), Vec3::splat(1.0)]]
+        );
+        assert_eq!(route.texture_file, "trail.png");
+        assert_eq!(route.display.anim_speed, Some(2.0));
+        assert_eq!(route.display.trail_scale, Some(3.0));
+
+        // A route inside a category belongs to it, and doesn't close it.
+        let node_id = pack.find_by_name(["B"].into_iter()).unwrap();
+        let b = pack.get(node_id).unwrap().data();
+        assert!(b.pois.is_empty());
+        assert_eq!(
+            b.trails[0].segments,
+            vec![vec![Vec3::splat(1.0), Vec3::splat(2.0)]]
+        );
+        assert!(pack.find_by_name(["B", "C"].into_iter()).is_some());
+    }
+
+    #[test]
+    fn test_nested_paths() {
+        let dir = tempdir().unwrap();
+        let pack_dir = dir.path().join("test");
+        std::fs::create_dir_all(pack_dir.join("Data").join("Icons")).unwrap();
+        std::fs::create_dir_all(pack_dir.join("Trails")).unwrap();
+        std::fs::write(
+            pack_dir.join("Data").join("a.xml"),
+            r#"
+<OverlayData>
+  <MarkerCategory name="A" iconFile=".\Icons\A.png">
+    <MarkerCategory name="B" iconFile="Trails/B.png" />
+  </MarkerCategory>
+  <POIs>
+    <POI MapID="15" xpos="1.0" ypos="1.0" zpos="1.0" type="A" iconFile="..\trails\b.PNG" />
+    <Trail type="A" trailData="Trails\A.trl" texture="icons/a.png" />
+  </POIs>
+</OverlayData>
+"#,
+        )
+        .unwrap();
+        let png = BASE64_STANDARD
+            .decode("iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==")
+            .unwrap();
+        std::fs::write(pack_dir.join("Data").join("Icons").join("A.png"), &png).unwrap();
+        std::fs::write(pack_dir.join("Trails").join("B.png"), &png).unwrap();
+        trail::to_file(
+            pack_dir.join("Trails").join("A.trl"),
+            &trail::TrailData {
+                version: 0,
+                map_id: 15,
+                segments: vec![vec![Vec3::ONE, Vec3::splat(2.0)]],
+            },
+        )
+        .unwrap();
+
+        let pack = read_marker_pack(&pack_dir).unwrap();
+        assert!(pack.diagnostics().is_empty());
+
+        // Relative to the XML file.
+        let node_id = pack.find_by_name(["A"].into_iter()).unwrap();
+        let a = pack.get(node_id).unwrap().data();
+        assert_eq!(
+            a.icon_file.as_ref().map(|path| path.as_str()),
+            Some("data/icons/a.png")
+        );
+        assert_eq!(
+            a.pois[0].icon_file.as_ref().map(|path| path.as_str()),
+            Some("trails/b.png")
+        );
+        assert_eq!(a.trails[0].texture_file, "data/icons/a.png");
+        assert!(pack.image_file("Data\\Icons\\A.png").is_some());
+
+        // Relative to the pack root when that's the only place it exists.
+        let node_id = pack.find_by_name(["A", "B"].into_iter()).unwrap();
+        let b = pack.get(node_id).unwrap().data();
+        assert_eq!(
+            b.icon_file.as_ref().map(|path| path.as_str()),
+            Some("trails/b.png")
+        );
+    }
+
+    #[test]
+    fn test_merge_packs() {
+        let dir = tempdir().unwrap();
+        let base_dir = dir.path().join("base");
+        std::fs::create_dir_all(&base_dir).unwrap();
+        std::fs::write(
+            base_dir.join("base.xml"),
+            r#"
+<OverlayData>
+  <MarkerCategory name="A" iconFile="a.png" texture="trail.png">
+    <MarkerCategory name="B" />
+  </MarkerCategory>
+</OverlayData>
+"#,
+        )
+        .unwrap();
+
+        let addon_dir = dir.path().join("addon");
+        std::fs::create_dir_all(&addon_dir).unwrap();
+        std::fs::write(
+            addon_dir.join("addon.xml"),
+            r#"
+<OverlayData>
+  <POIs>
+    <POI MapID="15" xpos="1.0" ypos="1.0" zpos="1.0" type="A.B" />
+    <POI MapID="15" xpos="1.0" ypos="1.0" zpos="1.0" type="C" />
+    <Trail type="A.B" trailData="b.trl" />
+  </POIs>
+</OverlayData>
+"#,
+        )
+        .unwrap();
+        trail::to_file(
+            addon_dir.join("b.trl"),
+            &trail::TrailData {
+                version: 0,
+                map_id: 15,
+                segments: vec![vec![Vec3::ONE, Vec3::splat(2.0)]],
+            },
+        )
+        .unwrap();
+
+        let addon = read_marker_pack(&addon_dir).unwrap();
+        assert!(!addon.diagnostics().has_errors());
+        assert_eq!(addon.orphans.pois.len(), 2);
+        assert_eq!(addon.orphans.trails.len(), 1);
+
+        let packs = MarkerPacks::new(HashMap::from_iter([
+            (PackId("base".into()), read_marker_pack(&base_dir).unwrap()),
+            (PackId("addon".into()), addon),
+        ]));
+        let base = packs.get(&PackId("base".into())).unwrap();
+        let node_id = base.find_by_name(["A", "B"].into_iter()).unwrap();
+        let marker = base.get(node_id).unwrap().data();
+        assert!(marker.map_ids.contains(&15));
+
+        assert_eq!(marker.pois.len(), 1);
+        let poi = &marker.pois[0];
+        assert_eq!(poi.from_pack, Some(PackId("addon".into())));
+        assert_eq!(
+            poi.icon_file.as_ref().map(|path| path.as_str()),
+            Some("a.png")
+        );
+
+        assert_eq!(marker.trails.len(), 1);
+        assert_eq!(marker.trails[0].texture_file, "trail.png");
+
+        let merged = packs.merged(&MarkerName::from(["A", "B"].into_iter()));
+        assert_eq!(merged.markers.len(), 1);
+        assert_eq!(
+            merged
+                .pois()
+                .map(|(pack_id, _)| pack_id)
+                .collect::<Vec<_>>(),
+            vec![&PackId("addon".into())]
+        );
+        assert_eq!(merged.trails().count(), 1);
+        assert!(packs
+            .merged(&MarkerName::from(["C"].into_iter()))
+            .is_empty());
+    }
+
     // #[test]
     // fn test_poi() {
     //     let pack = TEST_PACKS.get(&PackId("test.taco".into())).unwrap();
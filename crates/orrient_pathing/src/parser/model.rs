@@ -2,14 +2,22 @@ use std::str::FromStr as _;
 
 use anyhow::anyhow;
 use anyhow::Result;
-use bevy::log::warn;
+use bevy::color::Color;
 use bevy::math::Vec3;
 use bevy::utils::HashSet;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::attributes::Attribute;
 use quick_xml::events::attributes::Attributes;
+use serde::Deserialize;
+use serde::Serialize;
 use typed_path::Utf8PathBuf;
 use typed_path::Utf8UnixEncoding;
 use typed_path::Utf8WindowsPathBuf;
 
+use super::diagnostics::DiagnosticKind;
+use super::filter::Filters;
+use super::pack::PoiGuid;
+
 #[derive(Clone, Debug)]
 pub struct PoiXml {
     // type
@@ -20,27 +28,39 @@ pub struct PoiXml {
     pub position: Option<Vec3>,
     // iconFile
     pub icon_file: Option<Utf8PathBuf<Utf8UnixEncoding>>,
+    // behavior, resetLength
+    pub behavior: Option<Behavior>,
+    // GUID
+    pub guid: Option<PoiGuid>,
+    pub display: DisplayAttributes,
+    pub filters: Filters,
 }
 
 impl PoiXml {
-    pub(super) fn from_attrs(attrs: Attributes) -> Result<Self> {
+    pub(super) fn from_attrs(attrs: Attributes, issues: &mut Vec<DiagnosticKind>) -> Result<Self> {
         let mut map_id: Option<u32> = None;
         let mut x: Option<f32> = None;
         let mut y: Option<f32> = None;
         let mut z: Option<f32> = None;
         let mut id: Option<String> = None;
         let mut icon_file: Option<Utf8PathBuf<Utf8UnixEncoding>> = None;
+        let mut behavior: Option<u8> = None;
+        let mut reset_length: Option<f32> = None;
+        let mut guid: Option<PoiGuid> = None;
+        let mut display = DisplayAttributes::default();
+        let mut filters = Filters::default();
 
         for attr in attrs.filter_map(Result::ok) {
             let Ok(key) = String::from_utf8(attr.key.0.to_vec()) else {
-                warn!("Key is not UTF-8 encoded: {:?}", attr);
+                issues.push(DiagnosticKind::NonUtf8Attribute);
                 continue;
             };
 
-            let Ok(value) = String::from_utf8(attr.value.trim_ascii().to_vec()) else {
-                warn!("Value is not UTF-8 encoded: {:?}", attr);
+            let Some(value) = attr_value(&attr) else {
+                issues.push(DiagnosticKind::NonUtf8Attribute);
                 continue;
             };
+            let value = value.trim().to_string();
 
             match key.to_lowercase().as_str() {
                 "mapid" => {
@@ -62,8 +82,23 @@ impl PoiXml {
                     let path: Utf8WindowsPathBuf = Utf8PathBuf::from(value);
                     icon_file = Some(path.with_unix_encoding().to_path_buf());
                 }
-
-                _ => {}
+                "behavior" => {
+                    behavior = value.parse().ok();
+                }
+                "resetlength" => {
+                    reset_length = value.parse().ok();
+                }
+                "guid" => {
+                    guid = PoiGuid::from_base64(&value);
+                    if guid.is_none() {
+                        issues.push(DiagnosticKind::InvalidGuid(value));
+                    }
+                }
+                key => {
+                    if !display.parse(key, &value) {
+                        filters.parse(key, &value, issues);
+                    }
+                }
             }
         }
 
@@ -72,14 +107,17 @@ impl PoiXml {
         // then a xpos, ypos, zpos is either duplicate or missing.
         let num_coords = [x, y, z].into_iter().flatten().count();
         let position = if num_coords == 3 {
-            Some(Vec3::new(x.unwrap(), y.unwrap(), z.unwrap()))
+            let position = Vec3::new(x.unwrap(), y.unwrap(), z.unwrap());
+            // `f32` parses "NaN" and "inf", which can't be placed.
+            if position.is_finite() {
+                Some(position)
+            } else {
+                issues.push(DiagnosticKind::InvalidPosition(id.clone()));
+                None
+            }
         } else {
             if num_coords != 0 {
-                if let Some(ref id) = id {
-                    warn!("POI has an invalid position: {id}");
-                } else {
-                    warn!("POI has an invalid position.");
-                }
+                issues.push(DiagnosticKind::InvalidPosition(id.clone()));
             }
             None
         };
@@ -89,6 +127,11 @@ impl PoiXml {
             map_id,
             position,
             icon_file,
+            behavior: behavior
+                .and_then(|behavior| Behavior::from_attrs(behavior, reset_length, issues)),
+            guid,
+            display,
+            filters,
         })
     }
 }
@@ -96,29 +139,34 @@ impl PoiXml {
 #[derive(Clone, Debug)]
 pub(crate) struct TrailXml {
     // type
+    /// Empty when the trail is inside of the `MarkerCategory` it
+    /// belongs to.
     pub id: String,
     // trailData
     pub trail_file: String,
     // texture
     pub texture_file: Option<String>,
+    pub display: DisplayAttributes,
 }
 
 impl TrailXml {
-    pub(super) fn from_attrs(attrs: Attributes) -> Result<Self> {
+    pub(super) fn from_attrs(attrs: Attributes, issues: &mut Vec<DiagnosticKind>) -> Result<Self> {
         let mut id: Option<String> = None;
         let mut trail_file: Option<String> = None;
         let mut texture_file: Option<String> = None;
+        let mut display = DisplayAttributes::default();
 
         for attr in attrs.filter_map(Result::ok) {
             let Ok(key) = String::from_utf8(attr.key.0.to_vec()) else {
-                warn!("Key is not UTF-8 encoded: {:?}", attr);
+                issues.push(DiagnosticKind::NonUtf8Attribute);
                 continue;
             };
 
-            let Ok(value) = String::from_utf8(attr.value.trim_ascii().to_vec()) else {
-                warn!("Value is not UTF-8 encoded: {:?}", attr);
+            let Some(value) = attr_value(&attr) else {
+                issues.push(DiagnosticKind::NonUtf8Attribute);
                 continue;
             };
+            let value = value.trim().to_string();
 
             match key.to_lowercase().as_str() {
                 "type" => {
@@ -130,28 +178,77 @@ impl TrailXml {
                 "texture" => {
                     texture_file = value.parse().ok();
                 }
-                _ => {}
+                key => {
+                    display.parse(key, &value);
+                }
             }
         }
 
         Ok(Self {
-            id: id.ok_or(anyhow!("Trail missing field `trail.type`."))?,
+            id: id.unwrap_or_default(),
             trail_file: trail_file
                 .map(|file| file.to_lowercase())
                 .ok_or(anyhow!("Trail missing field `trailData`."))?,
             texture_file: texture_file.map(|file| file.to_lowercase()),
+            display,
         })
     }
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+/// The legacy TacO `Route` element. Instead of a `.trl` file, the path
+/// is made of the positions of the `POI` elements inside of it.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RouteXml {
+    // type
+    pub id: Option<String>,
+    // MapID
+    pub map_id: Option<u32>,
+    // texture
+    pub texture_file: Option<String>,
+    // BackwardDirection
+    pub backward: bool,
+    pub display: DisplayAttributes,
+}
+
+impl RouteXml {
+    pub(super) fn from_attrs(attrs: Attributes, issues: &mut Vec<DiagnosticKind>) -> Result<Self> {
+        let mut this = Self::default();
+
+        for attr in attrs.filter_map(Result::ok) {
+            let Ok(key) = String::from_utf8(attr.key.0.to_vec()) else {
+                issues.push(DiagnosticKind::NonUtf8Attribute);
+                continue;
+            };
+
+            let Some(value) = attr_value(&attr) else {
+                issues.push(DiagnosticKind::NonUtf8Attribute);
+                continue;
+            };
+            let value = value.trim().to_string();
+
+            match key.to_lowercase().as_str() {
+                "type" => this.id = Some(value),
+                "mapid" => this.map_id = value.parse().ok(),
+                "texture" => this.texture_file = Some(value.to_lowercase()),
+                "backwarddirection" => this.backward = parse_bool(&value).unwrap_or_default(),
+                key => {
+                    this.display.parse(key, &value);
+                }
+            }
+        }
+
+        Ok(this)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
 pub enum MarkerKind {
     #[default]
     Category,
     Separator,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Behavior {
     AlwaysVisible,             // 0
     ReappearOnMapChange,       // 1
@@ -163,6 +260,263 @@ pub enum Behavior {
     ReappearDailyPerCharacter, // 7
 }
 
+impl Behavior {
+    /// Build a [`Behavior`] from the numeric `behavior` attribute and
+    /// the optional `resetLength` attribute, in seconds, which is only
+    /// used by [`Behavior::ReappearAfterTime`].
+    pub fn from_attrs(
+        behavior: u8,
+        reset_length: Option<f32>,
+        issues: &mut Vec<DiagnosticKind>,
+    ) -> Option<Self> {
+        Some(match behavior {
+            0 => Behavior::AlwaysVisible,
+            1 => Behavior::ReappearOnMapChange,
+            2 => Behavior::ReappearDaily,
+            3 => Behavior::DisappearOnUse,
+            4 => Behavior::ReappearAfterTime(reset_length.unwrap_or_else(|| {
+                issues.push(DiagnosticKind::MissingResetLength);
+                0.0
+            })),
+            5 => Behavior::ReappearMapReset,
+            6 => Behavior::ReappearInstanceChange,
+            7 => Behavior::ReappearDailyPerCharacter,
+            unknown => {
+                issues.push(DiagnosticKind::UnknownBehavior(unknown));
+                return None;
+            }
+        })
+    }
+
+    /// The numeric `behavior` attribute.
+    pub fn id(&self) -> u8 {
+        match self {
+            Behavior::AlwaysVisible => 0,
+            Behavior::ReappearOnMapChange => 1,
+            Behavior::ReappearDaily => 2,
+            Behavior::DisappearOnUse => 3,
+            Behavior::ReappearAfterTime(_) => 4,
+            Behavior::ReappearMapReset => 5,
+            Behavior::ReappearInstanceChange => 6,
+            Behavior::ReappearDailyPerCharacter => 7,
+        }
+    }
+
+    /// The `resetLength` attribute, in seconds.
+    pub fn reset_length(&self) -> Option<f32> {
+        match self {
+            Behavior::ReappearAfterTime(reset_length) => Some(*reset_length),
+            _ => None,
+        }
+    }
+}
+
+/// Attributes that change how a POI or trail is displayed. These can be
+/// set on `MarkerCategory`, `POI` and `Trail` tags. The last few only
+/// apply to trails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DisplayAttributes {
+    // heightOffset
+    pub height_offset: Option<f32>,
+    // iconSize
+    pub icon_size: Option<f32>,
+    // alpha
+    pub alpha: Option<f32>,
+    // color
+    pub color: Option<Color>,
+    // tint
+    pub tint: Option<Color>,
+    // fadeNear
+    pub fade_near: Option<f32>,
+    // fadeFar
+    pub fade_far: Option<f32>,
+    // minSize
+    pub min_size: Option<f32>,
+    // maxSize
+    pub max_size: Option<f32>,
+    // scaleOnMapWithZoom
+    pub scale_on_map_with_zoom: Option<bool>,
+    // mapDisplaySize
+    pub map_display_size: Option<f32>,
+    // rotate, rotate-x, rotate-y, rotate-z
+    /// Fixed rotation in degrees. When set, the icon no longer faces
+    /// the camera.
+    pub rotate: Option<Vec3>,
+    // animSpeed
+    /// How fast the texture scrolls along a trail.
+    pub anim_speed: Option<f32>,
+    // trailScale
+    pub trail_scale: Option<f32>,
+    // isWall
+    /// Draw a trail upright instead of flat along the ground.
+    pub is_wall: Option<bool>,
+}
+
+impl DisplayAttributes {
+    /// Try to parse `key` as a display attribute. Returns false if the
+    /// key is not a display attribute.
+    pub(super) fn parse(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "heightoffset" => self.height_offset = value.parse().ok(),
+            "iconsize" => self.icon_size = value.parse().ok(),
+            "alpha" => self.alpha = value.parse().ok(),
+            "color" => self.color = parse_color(value),
+            "tint" => self.tint = parse_color(value),
+            "fadenear" => self.fade_near = value.parse().ok(),
+            "fadefar" => self.fade_far = value.parse().ok(),
+            "minsize" => self.min_size = value.parse().ok(),
+            "maxsize" => self.max_size = value.parse().ok(),
+            "scaleonmapwithzoom" => self.scale_on_map_with_zoom = parse_bool(value),
+            "mapdisplaysize" => self.map_display_size = value.parse().ok(),
+            "animspeed" => self.anim_speed = value.parse().ok(),
+            "trailscale" => self.trail_scale = value.parse().ok(),
+            "iswall" => self.is_wall = parse_bool(value),
+            "rotate" => {
+                let mut parts = value.split(',').map(|part| part.trim().parse::<f32>());
+                self.rotate = match (parts.next(), parts.next(), parts.next()) {
+                    (Some(Ok(x)), Some(Ok(y)), Some(Ok(z))) => Some(Vec3::new(x, y, z)),
+                    _ => None,
+                };
+            }
+            "rotate-x" => {
+                self.rotate.get_or_insert_with(Default::default).x =
+                    value.parse().unwrap_or_default()
+            }
+            "rotate-y" => {
+                self.rotate.get_or_insert_with(Default::default).y =
+                    value.parse().unwrap_or_default()
+            }
+            "rotate-z" => {
+                self.rotate.get_or_insert_with(Default::default).z =
+                    value.parse().unwrap_or_default()
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// The attributes that are set and differ from `parent`, as XML
+    /// key/value pairs. Attributes equal to `parent` would be inherited
+    /// anyway.
+    pub(super) fn attributes(&self, parent: &Self) -> Vec<(&'static str, String)> {
+        fn diff<T: PartialEq>(value: Option<T>, parent: Option<T>) -> Option<T> {
+            if value != parent {
+                value
+            } else {
+                None
+            }
+        }
+
+        let mut attributes = Vec::new();
+        let mut push = |key, value: Option<String>| {
+            if let Some(value) = value {
+                attributes.push((key, value));
+            }
+        };
+        let number = |value: Option<f32>| value.map(|value| value.to_string());
+        push(
+            "heightOffset",
+            number(diff(self.height_offset, parent.height_offset)),
+        );
+        push("iconSize", number(diff(self.icon_size, parent.icon_size)));
+        push("alpha", number(diff(self.alpha, parent.alpha)));
+        push("color", diff(self.color, parent.color).map(format_color));
+        push("tint", diff(self.tint, parent.tint).map(format_color));
+        push("fadeNear", number(diff(self.fade_near, parent.fade_near)));
+        push("fadeFar", number(diff(self.fade_far, parent.fade_far)));
+        push("minSize", number(diff(self.min_size, parent.min_size)));
+        push("maxSize", number(diff(self.max_size, parent.max_size)));
+        push(
+            "scaleOnMapWithZoom",
+            diff(self.scale_on_map_with_zoom, parent.scale_on_map_with_zoom)
+                .map(|value| (value as u8).to_string()),
+        );
+        push(
+            "mapDisplaySize",
+            number(diff(self.map_display_size, parent.map_display_size)),
+        );
+        push(
+            "rotate",
+            diff(self.rotate, parent.rotate)
+                .map(|rotate| format!("{},{},{}", rotate.x, rotate.y, rotate.z)),
+        );
+        push(
+            "animSpeed",
+            number(diff(self.anim_speed, parent.anim_speed)),
+        );
+        push(
+            "trailScale",
+            number(diff(self.trail_scale, parent.trail_scale)),
+        );
+        push(
+            "isWall",
+            diff(self.is_wall, parent.is_wall).map(|value| (value as u8).to_string()),
+        );
+        attributes
+    }
+
+    /// Fill in every attribute that isn't set with the value from
+    /// `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            height_offset: self.height_offset.or(other.height_offset),
+            icon_size: self.icon_size.or(other.icon_size),
+            alpha: self.alpha.or(other.alpha),
+            color: self.color.or(other.color),
+            tint: self.tint.or(other.tint),
+            fade_near: self.fade_near.or(other.fade_near),
+            fade_far: self.fade_far.or(other.fade_far),
+            min_size: self.min_size.or(other.min_size),
+            max_size: self.max_size.or(other.max_size),
+            scale_on_map_with_zoom: self.scale_on_map_with_zoom.or(other.scale_on_map_with_zoom),
+            map_display_size: self.map_display_size.or(other.map_display_size),
+            rotate: self.rotate.or(other.rotate),
+            anim_speed: self.anim_speed.or(other.anim_speed),
+            trail_scale: self.trail_scale.or(other.trail_scale),
+            is_wall: self.is_wall.or(other.is_wall),
+        }
+    }
+}
+
+/// The unescaped value of `attr`. Packs often have a bare `&` in names,
+/// so values that aren't valid XML escapes are kept as they are.
+fn attr_value(attr: &Attribute) -> Option<String> {
+    // `unescape_value` doesn't know the predefined entities like `&amp;`.
+    match attr.unescape_value_with(resolve_predefined_entity) {
+        Ok(value) => Some(value.into_owned()),
+        Err(_) => String::from_utf8(attr.value.to_vec()).ok(),
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse a hex color. Either `RRGGBB` or `AARRGGBB`, like TacO, with
+/// an optional leading `#`.
+fn parse_color(value: &str) -> Option<Color> {
+    let hex = value.trim_start_matches('#');
+    let color = u32::from_str_radix(hex, 16).ok()?;
+    let [a, r, g, b] = color.to_be_bytes();
+    match hex.len() {
+        6 => Some(Color::srgb_u8(r, g, b)),
+        8 => Some(Color::srgba_u8(r, g, b, a)),
+        _ => None,
+    }
+}
+
+/// Format a color as `AARRGGBB`, the inverse of [`parse_color`].
+fn format_color(color: Color) -> String {
+    let color = color.to_srgba();
+    let [r, g, b, a] = [color.red, color.green, color.blue, color.alpha]
+        .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+    format!("{a:02x}{r:02x}{g:02x}{b:02x}")
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct MarkerXml {
     pub name: String,
@@ -175,26 +529,32 @@ pub struct MarkerXml {
     pub map_ids: HashSet<u32>,
     pub icon_file: Option<Utf8PathBuf<Utf8UnixEncoding>>,
     pub texture: Option<String>,
+    pub display: DisplayAttributes,
+    pub filters: Filters,
 }
 
 impl MarkerXml {
-    pub fn from_attrs(attrs: Attributes) -> Result<Self> {
+    pub fn from_attrs(attrs: Attributes, issues: &mut Vec<DiagnosticKind>) -> Result<Self> {
         let mut this = Self::default();
+        let mut behavior: Option<u8> = None;
+        let mut reset_length: Option<f32> = None;
 
         for attr in attrs.filter_map(Result::ok) {
             let Ok(key) = String::from_utf8(attr.key.0.to_vec()) else {
-                warn!("Key is not UTF-8 encoded: {:?}", attr);
+                issues.push(DiagnosticKind::NonUtf8Attribute);
                 continue;
             };
 
-            let Ok(value) = String::from_utf8(attr.value.to_vec()) else {
-                warn!("Value is not UTF-8 encoded: {:?}", attr);
+            let Some(value) = attr_value(&attr) else {
+                issues.push(DiagnosticKind::NonUtf8Attribute);
                 continue;
             };
 
             match key.to_lowercase().as_str() {
                 "name" => this.name = value,
                 "displayname" => this.label = value,
+                "tip-name" => this.poi_tip = Some(value),
+                "tip-description" => this.poi_description = Some(value),
                 "isseparator" => match value.to_lowercase().as_str() {
                     "true" | "1" => this.kind = MarkerKind::Separator,
                     _ => {}
@@ -206,9 +566,211 @@ impl MarkerXml {
                 "texture" => {
                     this.texture = Some(value.to_lowercase());
                 }
-                _ => {}
+                "behavior" => {
+                    behavior = value.trim().parse().ok();
+                }
+                "resetlength" => {
+                    reset_length = value.trim().parse().ok();
+                }
+                key => {
+                    if !this.display.parse(key, value.trim()) {
+                        this.filters.parse(key, value.trim(), issues);
+                    }
+                }
             }
         }
+        this.behavior =
+            behavior.and_then(|behavior| Behavior::from_attrs(behavior, reset_length, issues));
         Ok(this)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use orrient_link::Profession;
+    use quick_xml::events::BytesStart;
+
+    use crate::parser::filter::MapType;
+    use crate::parser::filter::Mount;
+
+    fn element(content: &str) -> BytesStart<'_> {
+        let name_len = content.find(' ').unwrap_or(content.len());
+        BytesStart::from_content(content, name_len)
+    }
+
+    #[test]
+    fn test_marker_behavior() {
+        let marker = MarkerXml::from_attrs(
+            element(r#"MarkerCategory name="a" behavior="4" resetLength="60""#).attributes(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert!(matches!(
+            marker.behavior,
+            Some(Behavior::ReappearAfterTime(reset)) if reset == 60.0
+        ));
+
+        let mut issues = vec![];
+        let marker = MarkerXml::from_attrs(
+            element(r#"MarkerCategory name="a" behavior="9""#).attributes(),
+            &mut issues,
+        )
+        .unwrap();
+        assert!(marker.behavior.is_none());
+        assert_eq!(issues, vec![DiagnosticKind::UnknownBehavior(9)]);
+    }
+
+    #[test]
+    fn test_poi_behavior() {
+        let poi = PoiXml::from_attrs(
+            element(r#"POI type="a" behavior="3""#).attributes(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert!(matches!(poi.behavior, Some(Behavior::DisappearOnUse)));
+
+        let poi = PoiXml::from_attrs(element(r#"POI type="a""#).attributes(), &mut vec![]).unwrap();
+        assert!(poi.behavior.is_none());
+    }
+
+    #[test]
+    fn test_poi_guid() {
+        let poi = PoiXml::from_attrs(
+            element(r#"POI type="a" GUID="AAECAwQFBgcICQoLDA0ODw==""#).attributes(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(
+            poi.guid,
+            Some(PoiGuid([
+                0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+            ]))
+        );
+
+        let mut issues = vec![];
+        let poi = PoiXml::from_attrs(
+            element(r#"POI type="a" GUID="none""#).attributes(),
+            &mut issues,
+        )
+        .unwrap();
+        assert!(poi.guid.is_none());
+        assert_eq!(issues, vec![DiagnosticKind::InvalidGuid("none".into())]);
+    }
+
+    #[test]
+    fn test_poi_position() {
+        let poi = PoiXml::from_attrs(
+            element(r#"POI type="a" xpos="1" ypos="2" zpos="3""#).attributes(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(poi.position, Some(Vec3::new(1.0, 2.0, 3.0)));
+
+        let mut issues = vec![];
+        let poi = PoiXml::from_attrs(
+            element(r#"POI type="a" xpos="1" ypos="NaN" zpos="inf""#).attributes(),
+            &mut issues,
+        )
+        .unwrap();
+        assert!(poi.position.is_none());
+        assert_eq!(
+            issues,
+            vec![DiagnosticKind::InvalidPosition(Some("a".into()))]
+        );
+    }
+
+    #[test]
+    fn test_display_attributes() {
+        let marker = MarkerXml::from_attrs(
+            element(
+                r#"MarkerCategory name="a" heightOffset="2.5" iconSize="0.5" alpha="0.75" color="80ff0000" scaleOnMapWithZoom="0" rotate-y="90""#,
+            )
+            .attributes(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(marker.display.height_offset, Some(2.5));
+        assert_eq!(marker.display.icon_size, Some(0.5));
+        assert_eq!(marker.display.alpha, Some(0.75));
+        assert_eq!(
+            marker.display.color,
+            Some(Color::srgba_u8(0xff, 0x00, 0x00, 0x80))
+        );
+        assert_eq!(marker.display.scale_on_map_with_zoom, Some(false));
+        assert_eq!(marker.display.rotate, Some(Vec3::new(0.0, 90.0, 0.0)));
+
+        let poi = PoiXml::from_attrs(
+            element(r#"POI type="a" tint="00ff00" rotate="1,2,3" fadeNear="100" fadeFar="200""#)
+                .attributes(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(poi.display.tint, Some(Color::srgb_u8(0x00, 0xff, 0x00)));
+        assert_eq!(poi.display.rotate, Some(Vec3::new(1.0, 2.0, 3.0)));
+        assert_eq!(poi.display.fade_near, Some(100.0));
+        assert_eq!(poi.display.fade_far, Some(200.0));
+    }
+
+    #[test]
+    fn test_filters() {
+        let marker = MarkerXml::from_attrs(
+            element(r#"MarkerCategory name="a" profession="Guardian,Mesmer" alpha="0.5""#)
+                .attributes(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(marker.display.alpha, Some(0.5));
+        assert_eq!(
+            marker.filters.professions,
+            Some(vec![Profession::Guardian, Profession::Mesmer])
+        );
+
+        let mut issues = vec![];
+        let poi = PoiXml::from_attrs(
+            element(r#"POI type="a" mount="Springer,Pony" mapType="5""#).attributes(),
+            &mut issues,
+        )
+        .unwrap();
+        assert_eq!(poi.filters.mounts, Some(vec![Mount::Springer]));
+        assert_eq!(poi.filters.map_types, Some(vec![MapType(5)]));
+        assert_eq!(
+            issues,
+            vec![DiagnosticKind::UnknownFilter {
+                key: "mount".into(),
+                value: "Pony".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_trail_attributes() {
+        let trail = TrailXml::from_attrs(
+            element(
+                r#"Trail type="a" trailData="a.trl" animSpeed="2" trailScale="0.5" color="ff0000" alpha="0.5" fadeNear="10" fadeFar="20" isWall="1""#,
+            )
+            .attributes(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(trail.display.anim_speed, Some(2.0));
+        assert_eq!(trail.display.trail_scale, Some(0.5));
+        assert_eq!(trail.display.color, Some(Color::srgb_u8(0xff, 0x00, 0x00)));
+        assert_eq!(trail.display.alpha, Some(0.5));
+        assert_eq!(trail.display.fade_near, Some(10.0));
+        assert_eq!(trail.display.fade_far, Some(20.0));
+        assert_eq!(trail.display.is_wall, Some(true));
+
+        let route = RouteXml::from_attrs(
+            element(r#"Route type="a" MapID="15" BackwardDirection="1" animSpeed="0""#)
+                .attributes(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(route.id.as_deref(), Some("a"));
+        assert_eq!(route.map_id, Some(15));
+        assert!(route.backward);
+        assert_eq!(route.display.anim_speed, Some(0.0));
+    }
+}
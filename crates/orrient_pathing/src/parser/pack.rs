@@ -1,3 +1,5 @@
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine as _;
 use bevy::utils::HashMap;
 use bevy::{prelude::*, utils::HashSet};
 use serde::{Deserialize, Serialize};
@@ -5,19 +7,96 @@ use slab_tree::{NodeId, NodeRef, Tree};
 use typed_path::Utf8PathBuf;
 use typed_path::Utf8UnixEncoding;
 
+use super::diagnostics::DiagnosticKind;
+use super::diagnostics::Location;
+use super::diagnostics::PackDiagnostics;
+use super::filter::Filters;
 use super::model::Behavior;
+use super::model::DisplayAttributes;
 use super::model::MarkerKind;
 use super::model::MarkerXml;
 use super::model::PoiXml;
+use super::model::RouteXml;
 use super::model::TrailXml;
 use super::trail::TrailData;
 use super::PackId;
 
-#[derive(Clone, Debug)]
+/// Identifies a single POI across all marker packs. Read from the
+/// base64 encoded `GUID` attribute.
+#[derive(Component, Hash, Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoiGuid(pub [u8; 16]);
+
+impl PoiGuid {
+    pub fn from_base64(value: &str) -> Option<Self> {
+        let bytes = BASE64_STANDARD.decode(value).ok()?;
+        Some(Self(bytes.try_into().ok()?))
+    }
+
+    /// Derive a GUID for a POI that didn't define one. The same
+    /// inputs always generate the same GUID, so it's stable across
+    /// reloads. `nth` counts the POIs with the same inputs that came
+    /// before this one, so POIs stacked on the same spot still get
+    /// their own GUID.
+    pub fn generate(id: &str, map_id: Option<u32>, position: Option<Vec3>, nth: u32) -> Self {
+        // 128-bit FNV-1a
+        const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+        const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+        let bytes = id
+            .bytes()
+            .chain(map_id.unwrap_or_default().to_le_bytes())
+            .chain(
+                position
+                    .unwrap_or_default()
+                    .to_array()
+                    .into_iter()
+                    .flat_map(f32::to_le_bytes),
+            )
+            .chain(nth.to_le_bytes());
+
+        let mut hash = OFFSET_BASIS;
+        for byte in bytes {
+            hash ^= byte as u128;
+            hash = hash.wrapping_mul(PRIME);
+        }
+        Self(hash.to_le_bytes())
+    }
+}
+
+impl std::fmt::Display for PoiGuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        BASE64_STANDARD.encode(self.0).fmt(f)
+    }
+}
+
+/// A single POI. Any attribute the POI doesn't set itself is resolved
+/// from its [`Marker`] when the [`MarkerPack`] is built.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Poi {
+    pub guid: PoiGuid,
     pub map_id: Option<u32>,
     pub position: Option<Vec3>,
+    #[serde(with = "super::cache::unix_path")]
     pub icon_file: Option<Utf8PathBuf<Utf8UnixEncoding>>,
+    pub behavior: Option<Behavior>,
+    pub display: DisplayAttributes,
+    pub filters: Filters,
+    /// The pack that added this POI to a category defined by another
+    /// pack. Its files are looked up in that pack first.
+    pub from_pack: Option<PackId>,
+}
+
+impl Poi {
+    pub(super) fn inherit(&mut self, marker: &Marker) {
+        if self.icon_file.is_none() {
+            self.icon_file = marker.icon_file.clone();
+        }
+        if self.behavior.is_none() {
+            self.behavior = marker.behavior;
+        }
+        self.display = self.display.or(marker.display);
+        self.filters = std::mem::take(&mut self.filters).or(marker.filters.clone());
+    }
 }
 
 #[derive(Hash, Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -163,7 +242,7 @@ impl FullMarkerId {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Marker {
     pub name: String,
     pub label: String,
@@ -173,8 +252,11 @@ pub struct Marker {
     pub poi_tip: Option<String>,
     pub poi_description: Option<String>,
     pub map_ids: HashSet<u32>,
+    #[serde(with = "super::cache::unix_path")]
     pub icon_file: Option<Utf8PathBuf<Utf8UnixEncoding>>,
     pub texture: Option<String>,
+    pub display: DisplayAttributes,
+    pub filters: Filters,
 
     /// Associated trails
     pub trails: Vec<Route>,
@@ -183,8 +265,45 @@ pub struct Marker {
     pub pois: Vec<Poi>,
 }
 
+/// The attributes a [`Marker`] passes down to its children.
+#[derive(Clone, Debug)]
+struct Inherited {
+    icon_file: Option<Utf8PathBuf<Utf8UnixEncoding>>,
+    texture: Option<String>,
+    behavior: Option<Behavior>,
+    display: DisplayAttributes,
+    filters: Filters,
+}
+
 impl Marker {
+    fn inherited(&self) -> Inherited {
+        Inherited {
+            icon_file: self.icon_file.clone(),
+            texture: self.texture.clone(),
+            behavior: self.behavior,
+            display: self.display,
+            filters: self.filters.clone(),
+        }
+    }
+
+    fn inherit(&mut self, parent: Inherited) {
+        if self.icon_file.is_none() {
+            self.icon_file = parent.icon_file;
+        }
+        if self.texture.is_none() {
+            self.texture = parent.texture;
+        }
+        if self.behavior.is_none() {
+            self.behavior = parent.behavior;
+        }
+        self.display = self.display.or(parent.display);
+        self.filters = std::mem::take(&mut self.filters).or(parent.filters);
+    }
+
     pub fn merge(&mut self, other: Marker) {
+        if self.label.is_empty() {
+            self.label = other.label;
+        }
         if self.behavior.is_none() {
             self.behavior = other.behavior;
         }
@@ -203,22 +322,101 @@ impl Marker {
         if self.texture.is_none() {
             self.texture = other.texture;
         }
+        self.display = self.display.or(other.display);
+        self.filters = std::mem::take(&mut self.filters).or(other.filters);
     }
 }
 
-#[derive(Asset, Reflect, Clone, Debug)]
+#[derive(Asset, Reflect, Clone, Debug, Serialize, Deserialize)]
 pub struct Route {
     pub map_id: u32,
-    pub path: Vec<Vec3>,
+    /// Disconnected parts of the route, each drawn on its own.
+    pub segments: Vec<Vec<Vec3>>,
     pub texture_file: String,
+    /// Resolved from the trail's category.
+    #[reflect(ignore)]
+    pub display: DisplayAttributes,
+    /// The pack that added this route to a category defined by another
+    /// pack. Its files are looked up in that pack first.
+    #[reflect(ignore)]
+    pub from_pack: Option<PackId>,
+}
+
+/// A trail whose category isn't defined in its own pack.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OrphanTrail {
+    pub map_id: u32,
+    pub segments: Vec<Vec<Vec3>>,
+    /// When unset, the texture of the category it's attached to is used.
+    pub texture_file: Option<String>,
+    /// Only the trail's own attributes. The rest are inherited once
+    /// it's attached.
+    pub display: DisplayAttributes,
+}
+
+/// POIs and trails whose category isn't defined in their own pack. Add-on
+/// packs use these to add to another pack's categories, so they're
+/// attached to the category of the same name when [`MarkerPacks`] is
+/// built.
+///
+/// [`MarkerPacks`]: super::MarkerPacks
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Orphans {
+    pub pois: Vec<(MarkerName, Poi)>,
+    pub trails: Vec<(MarkerName, OrphanTrail)>,
+}
+
+/// A category merged across every pack that defines it.
+#[derive(Debug)]
+pub struct MergedCategory<'a> {
+    pub name: MarkerName,
+    /// Each pack's definition of the category.
+    pub markers: Vec<(&'a PackId, &'a Marker)>,
+}
+
+impl<'a> MergedCategory<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+
+    /// Every POI in the category with the pack it came from.
+    pub fn pois(&self) -> impl Iterator<Item = (&'a PackId, &'a Poi)> + use<'a, '_> {
+        self.markers.iter().flat_map(|&(pack_id, marker)| {
+            marker
+                .pois
+                .iter()
+                .map(move |poi| (poi.from_pack.as_ref().unwrap_or(pack_id), poi))
+        })
+    }
+
+    /// Every trail in the category with the pack it came from.
+    pub fn trails(&self) -> impl Iterator<Item = (&'a PackId, &'a Route)> + use<'a, '_> {
+        self.markers.iter().flat_map(|&(pack_id, marker)| {
+            marker
+                .trails
+                .iter()
+                .map(move |route| (route.from_pack.as_ref().unwrap_or(pack_id), route))
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct MarkerPack {
     pub tree: Tree<Marker>,
 
-    /// path->icon references
-    icons: HashMap<String, Handle<Image>>,
+    /// The pack's image files by lowercased path, still encoded. They're
+    /// only decoded by [`PackTextures`] once something that uses them is
+    /// spawned.
+    ///
+    /// [`PackTextures`]: super::textures::PackTextures
+    pub(super) image_files: HashMap<String, Vec<u8>>,
+
+    /// Problems found while parsing
+    pub(super) diagnostics: PackDiagnostics,
+
+    /// POIs and trails waiting to be attached to another pack's
+    /// categories.
+    pub(super) orphans: Orphans,
 }
 
 impl std::ops::Deref for MarkerPack {
@@ -236,10 +434,12 @@ impl std::ops::DerefMut for MarkerPack {
 }
 
 impl MarkerPack {
-    fn new(tree: Tree<Marker>) -> Self {
+    pub(super) fn new(tree: Tree<Marker>) -> Self {
         Self {
             tree,
-            icons: Default::default(),
+            image_files: Default::default(),
+            diagnostics: Default::default(),
+            orphans: Default::default(),
         }
     }
 
@@ -291,15 +491,45 @@ impl MarkerPack {
         false
     }
 
-    pub fn get_image(&self, path: &str) -> Option<Handle<Image>> {
-        self.icons.get(path).cloned()
+    /// The encoded image file at `path`.
+    pub fn image_file(&self, path: &str) -> Option<&[u8]> {
+        self.image_files
+            .get(&Self::normalize_path(path))
+            .map(Vec::as_slice)
     }
 
-    pub fn get_images(&self) -> impl Iterator<Item = &Handle<Image>> {
-        self.icons.values()
+    /// The form every file path in a pack is stored and looked up in.
+    /// Packs are made for Windows, so paths are case-folded, `\` is
+    /// treated as a separator and `.` and `..` are resolved.
+    pub fn normalize_path(path: &str) -> String {
+        let path = path.to_lowercase().replace('\\', "/");
+        let mut parts: Vec<&str> = vec![];
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    parts.pop();
+                }
+                part => parts.push(part),
+            }
+        }
+        parts.join("/")
     }
 
-    pub fn find_by_name<'a>(&self, name: impl Into<MarkerName>) -> Option<NodeId> {
+    /// Resolve `path` from a tag in `xml_file` relative to the XML
+    /// file's directory.
+    pub fn resolve_path(xml_file: &str, path: &str) -> String {
+        match xml_file.replace('\\', "/").rsplit_once('/') {
+            Some((dir, _)) => Self::normalize_path(&format!("{dir}/{path}")),
+            None => Self::normalize_path(path),
+        }
+    }
+
+    pub fn diagnostics(&self) -> &PackDiagnostics {
+        &self.diagnostics
+    }
+
+    pub fn find_by_name(&self, name: impl Into<MarkerName>) -> Option<NodeId> {
         let mut current = self.root()?;
         for part in name.into().0.iter() {
             if let Some(found) = current.children().find(|node| part == &node.data().name) {
@@ -319,15 +549,33 @@ pub struct MarkerPackBuilder {
 
     /// Store the found poi tags to be handler after all markers have
     /// been found.
-    poi_tags: Vec<PoiXml>,
+    poi_tags: Vec<(PoiXml, Location)>,
 
     /// Store the found trail tags to be handler after all markers
     /// have been found.
-    trail_tags: Vec<TrailXml>,
+    trail_tags: Vec<(TrailXml, Location)>,
+
+    /// The `Route` tag being read, with the `POI` tags inside of it so
+    /// far.
+    route: Option<(RouteXml, Vec<PoiXml>, Location)>,
+
+    /// Number of `Route` tags read, used to name their trail data.
+    route_count: usize,
 
     /// Store the found trail files to be handler after all markers
     /// have been found.
     trail_data: HashMap<String, TrailData>,
+
+    /// Every file in the pack, lowercased.
+    files: HashSet<String>,
+
+    /// Icons and textures referenced by tags, checked against `files`
+    /// once the whole pack has been read.
+    file_refs: Vec<(String, Location)>,
+
+    /// Paths resolved relative to a nested XML file, mapped to the same
+    /// path relative to the pack root.
+    root_paths: HashMap<String, String>,
 }
 
 impl MarkerPackBuilder {
@@ -342,67 +590,200 @@ impl MarkerPackBuilder {
             parents: vec![root_id],
             poi_tags: Default::default(),
             trail_tags: Default::default(),
+            route: None,
+            route_count: 0,
             trail_data: Default::default(),
+            files: Default::default(),
+            file_refs: Default::default(),
+            root_paths: Default::default(),
+        }
+    }
+
+    pub fn report(&mut self, location: &Location, kind: DiagnosticKind) {
+        self.pack.diagnostics.push(location, kind);
+    }
+
+    /// Add a POI, or a point of the route being read when inside a
+    /// `Route` tag.
+    pub fn add_poi(&mut self, poi: PoiXml, location: Location) {
+        if let Some((_, points, _)) = &mut self.route {
+            points.push(poi);
+            return;
         }
+        self.poi_tags.push((poi, location));
     }
 
-    pub fn add_poi(&mut self, poi: PoiXml) {
-        self.poi_tags.push(poi);
+    pub fn add_trail_tag(&mut self, trail: TrailXml, location: Location) {
+        self.trail_tags.push((trail, location));
     }
 
-    pub fn add_trail_tag(&mut self, trail: TrailXml) {
-        self.trail_tags.push(trail);
+    pub fn start_route(&mut self, route: RouteXml, location: Location) {
+        self.route = Some((route, vec![], location));
+    }
+
+    /// Turn the `Route` tag being read into a trail through the
+    /// positions of its POIs.
+    pub fn end_route(&mut self) {
+        let Some((route, points, location)) = self.route.take() else {
+            return;
+        };
+
+        let Some(id) = route
+            .id
+            .or_else(|| points.first().map(|point| point.id.clone()))
+        else {
+            self.report(
+                &location,
+                DiagnosticKind::InvalidTag {
+                    tag: "Route".into(),
+                    error: "Route missing field `type`.".into(),
+                },
+            );
+            return;
+        };
+
+        let Some(map_id) = route
+            .map_id
+            .or_else(|| points.iter().find_map(|point| point.map_id))
+        else {
+            self.report(
+                &location,
+                DiagnosticKind::InvalidTag {
+                    tag: "Route".into(),
+                    error: "Route missing field `MapID`.".into(),
+                },
+            );
+            return;
+        };
+
+        let mut path = points
+            .into_iter()
+            .filter_map(|point| point.position)
+            .collect::<Vec<_>>();
+        if route.backward {
+            path.reverse();
+        }
+
+        // A route has no trail file, so its points are stored under a
+        // name no file in the pack can have.
+        let trail_file = format!("{}#route{}", location.file, self.route_count);
+        self.route_count += 1;
+        self.add_trail_data(
+            trail_file.clone(),
+            TrailData {
+                version: 0,
+                map_id,
+                segments: if path.is_empty() { vec![] } else { vec![path] },
+            },
+        );
+        self.add_trail_tag(
+            TrailXml {
+                id,
+                trail_file: MarkerPack::normalize_path(&trail_file),
+                texture_file: route.texture_file,
+                display: route.display,
+            },
+            location,
+        );
+    }
+
+    pub fn add_file(&mut self, file_path: &str) {
+        self.files.insert(MarkerPack::normalize_path(file_path));
+    }
+
+    /// Resolve `path` from a tag in `xml_file` with
+    /// [`MarkerPack::resolve_path`].
+    pub fn resolve_path(&mut self, xml_file: &str, path: &str) -> String {
+        let resolved = MarkerPack::resolve_path(xml_file, path);
+        // Some packs use paths relative to the pack root in nested XML
+        // files. Keep that version in case the file only exists there.
+        let from_root = MarkerPack::normalize_path(path);
+        if resolved != from_root {
+            self.root_paths.insert(resolved.clone(), from_root);
+        }
+        resolved
+    }
+
+    /// Resolve a reference to an icon or texture from a tag at
+    /// `location`. It's reported if the pack doesn't contain it.
+    pub fn resolve_file(&mut self, path: &str, location: &Location) -> String {
+        let resolved = self.resolve_path(&location.file, path);
+        self.file_refs.push((resolved.clone(), location.clone()));
+        resolved
     }
 
     pub fn add_trail_data(&mut self, file_path: String, data: TrailData) {
-        if self.trail_data.insert(file_path.to_lowercase(), data).is_some() {
-            warn!("{file_path} already exists!");
+        if self
+            .trail_data
+            .insert(MarkerPack::normalize_path(&file_path), data)
+            .is_some()
+        {
+            self.report(&Location::file(file_path), DiagnosticKind::DuplicateFile);
         }
     }
 
-    pub fn add_image(&mut self, file_path: String, image: Image, image_assets: &mut Assets<Image>) {
+    pub fn add_image(&mut self, file_path: String, data: Vec<u8>) {
         debug!(
             "Found image: {pack_id}/{file_path}",
             pack_id = self.pack.id()
         );
-        let handle = image_assets.add(image);
-        self.pack.icons.insert(file_path.to_lowercase(), handle);
+        self.pack
+            .image_files
+            .insert(MarkerPack::normalize_path(&file_path), data);
     }
 
     pub fn add_marker(&mut self, xml: MarkerXml) -> NodeId {
-        let parent_id = self.parents.last().unwrap();
-        let id = if let Some(node) = self
+        let parent_id = *self.parents.last().unwrap();
+        let marker = Marker {
+            name: xml.name,
+            label: xml.label,
+            kind: xml.kind,
+            depth: xml.depth,
+            behavior: xml.behavior,
+            poi_tip: xml.poi_tip,
+            poi_description: xml.poi_description,
+            map_ids: xml.map_ids,
+            icon_file: xml.icon_file,
+            texture: xml.texture,
+            display: xml.display,
+            filters: xml.filters,
+            trails: vec![],
+            pois: vec![],
+        };
+        let existing = self
             .pack
-            .get(*parent_id)
+            .get(parent_id)
             .unwrap()
             .children()
-            .find(|node| node.data().name == xml.name)
-        {
-            node.node_id()
+            .find(|node| node.data().name == marker.name)
+            .map(|node| node.node_id());
+        let id = if let Some(id) = existing {
+            // The same category can be defined again, like in another
+            // XML file, so every definition adds its attributes.
+            self.pack.get_mut(id).unwrap().data().merge(marker);
+            id
         } else {
             self.pack
-                .get_mut(*parent_id)
+                .get_mut(parent_id)
                 .unwrap()
-                .append(Marker {
-                    name: xml.name,
-                    label: xml.label,
-                    kind: xml.kind,
-                    depth: xml.depth,
-                    behavior: xml.behavior,
-                    poi_tip: xml.poi_tip,
-                    poi_description: xml.poi_description,
-                    map_ids: xml.map_ids,
-                    icon_file: xml.icon_file,
-                    texture: xml.texture,
-                    trails: vec![],
-                    pois: vec![],
-                })
+                .append(marker)
                 .node_id()
         };
         self.parents.push(id);
         id
     }
 
+    /// The full name of the `MarkerCategory` tag being read, if any.
+    /// Trails inside of a category belong to it when they don't name
+    /// their own.
+    pub fn current_category(&self) -> Option<String> {
+        let parent_id = *self.parents.last()?;
+        if Some(parent_id) == self.pack.root_id() {
+            return None;
+        }
+        Some(self.pack.name_of(parent_id).to_string())
+    }
+
     pub fn new_root(&mut self) {
         self.parents.clear();
         self.parents.push(self.pack.root_id().unwrap())
@@ -414,18 +795,135 @@ impl MarkerPackBuilder {
         }
     }
 
+    /// Resolve the attributes of every [`Marker`] from its ancestors.
+    fn inherit_attributes(&mut self) {
+        let root_id = self.pack.root_id().unwrap();
+        // Pre-order visits every parent before its children, so the
+        // parent is always resolved by the time it's inherited from.
+        let node_ids = self
+            .pack
+            .recurse(root_id)
+            .map(|node| node.node_id())
+            .collect::<Vec<_>>();
+        for node_id in node_ids {
+            let Some(parent) = self
+                .pack
+                .get(node_id)
+                .and_then(|node| node.parent().map(|parent| parent.data().inherited()))
+            else {
+                continue;
+            };
+            if let Some(mut node) = self.pack.get_mut(node_id) {
+                node.data().inherit(parent);
+            }
+        }
+    }
+
+    /// Switch paths that don't exist relative to their XML file over to
+    /// the pack root relative version, if that one does.
+    fn fix_root_paths(&mut self) {
+        let root_paths = std::mem::take(&mut self.root_paths);
+        let files = &self.files;
+        let fix = |path: &mut String| {
+            if files.contains(path) {
+                return;
+            }
+            if let Some(from_root) = root_paths.get(path).filter(|path| files.contains(*path)) {
+                *path = from_root.clone();
+            }
+        };
+
+        let node_ids = self
+            .pack
+            .recurse(self.pack.root_id().unwrap())
+            .map(|node| node.node_id())
+            .collect::<Vec<_>>();
+        for node_id in node_ids {
+            let mut node = self.pack.get_mut(node_id).unwrap();
+            let marker = node.data();
+            if let Some(icon_file) = &mut marker.icon_file {
+                let mut path = icon_file.as_str().to_string();
+                fix(&mut path);
+                *icon_file = path.into();
+            }
+            if let Some(texture) = &mut marker.texture {
+                fix(texture);
+            }
+        }
+        for (poi, _) in &mut self.poi_tags {
+            if let Some(icon_file) = &mut poi.icon_file {
+                let mut path = icon_file.as_str().to_string();
+                fix(&mut path);
+                *icon_file = path.into();
+            }
+        }
+        for (trail, _) in &mut self.trail_tags {
+            fix(&mut trail.trail_file);
+            if let Some(texture_file) = &mut trail.texture_file {
+                fix(texture_file);
+            }
+        }
+        for (file_path, _) in &mut self.file_refs {
+            fix(file_path);
+        }
+    }
+
     pub fn build(mut self) -> MarkerPack {
-        let pack_id = self.pack.id().to_owned();
+        self.fix_root_paths();
+        self.inherit_attributes();
+
+        let file_refs = std::mem::take(&mut self.file_refs);
+        for (file_path, location) in file_refs {
+            if !self.files.contains(&file_path) {
+                self.report(&location, DiagnosticKind::MissingFile(file_path));
+            }
+        }
 
         // Attach POI's
+        let mut guids: HashSet<PoiGuid> = Default::default();
         let pois = self.poi_tags.drain(..).collect::<Vec<_>>();
-        for poi in pois {
+        for (poi, location) in pois {
+            let guid = match poi.guid {
+                Some(guid) => {
+                    if !guids.insert(guid) {
+                        self.report(
+                            &location,
+                            DiagnosticKind::DuplicateGuid { id: poi.id, guid },
+                        );
+                        continue;
+                    }
+                    guid
+                }
+                // Only a GUID from the pack can really be duplicated, so
+                // generated ones are made unique instead.
+                None => (0..)
+                    .map(|nth| PoiGuid::generate(&poi.id, poi.map_id, poi.position, nth))
+                    .find(|guid| guids.insert(*guid))
+                    .unwrap(),
+            };
+
+            let id = poi.id;
+            let mut poi = Poi {
+                guid,
+                map_id: poi.map_id,
+                position: poi.position,
+                icon_file: poi.icon_file,
+                behavior: poi.behavior,
+                display: poi.display,
+                filters: poi.filters,
+                from_pack: None,
+            };
+
             let Some(mut node) = self
                 .pack
-                .find_by_name(poi.id.split("."))
+                .find_by_name(id.split("."))
                 .and_then(|node_id| self.pack.get_mut(node_id))
             else {
-                warn!("Could not find Marker for Poi id: {}", poi.id);
+                self.pack
+                    .orphans
+                    .pois
+                    .push((MarkerName::from(id.split(".")), poi));
+                self.report(&location, DiagnosticKind::MissingMarker(id));
                 continue;
             };
             let marker = node.data();
@@ -434,50 +932,68 @@ impl MarkerPackBuilder {
                 marker.map_ids.insert(map_id);
             }
 
-            marker.pois.push(Poi {
-                map_id: poi.map_id,
-                position: poi.position,
-                icon_file: poi.icon_file,
-            });
+            poi.inherit(marker);
+            marker.pois.push(poi);
         }
 
         // Attach trail tags
         let trails = self.trail_tags.drain(..).collect::<Vec<_>>();
         info!("Loading Trails...");
-        for trail in trails {
+        for (trail, location) in trails {
             let Some(data) = self.trail_data.get(&trail.trail_file) else {
-                warn!(
-                    "Associated TrailData not found for {:?}:{:?}: {}",
-                    pack_id, trail.id, trail.trail_file
+                self.report(
+                    &location,
+                    DiagnosticKind::MissingTrailData {
+                        id: trail.id,
+                        trail_file: trail.trail_file,
+                    },
                 );
                 continue;
             };
 
+            if data.segments.is_empty() {
+                self.report(
+                    &location,
+                    DiagnosticKind::EmptyTrail {
+                        id: trail.id,
+                        trail_file: trail.trail_file,
+                    },
+                );
+                continue;
+            }
+
             let Some(mut node) = self
                 .pack
                 .find_by_name(trail.id.split("."))
                 .and_then(|node_id| self.pack.get_mut(node_id))
             else {
-                warn!("Could not find Marker for Trail id: {}", trail.id);
+                self.pack.orphans.trails.push((
+                    MarkerName::from(trail.id.split(".")),
+                    OrphanTrail {
+                        map_id: data.map_id,
+                        segments: data.segments.clone(),
+                        texture_file: trail.texture_file,
+                        display: trail.display,
+                    },
+                ));
+                self.report(&location, DiagnosticKind::MissingMarker(trail.id));
                 continue;
             };
             let marker = node.data();
 
             marker.map_ids.insert(data.map_id);
 
-            let Some(texture) = trail
-                .texture_file
-                .as_ref()
-                .or_else(|| marker.texture.as_ref())
-            else {
-                warn!("Trail has no texture: {}", trail.id);
+            let Some(texture) = trail.texture_file.as_ref().or(marker.texture.as_ref()) else {
+                self.report(&location, DiagnosticKind::MissingTexture(trail.id));
                 continue;
             };
 
             let route = Route {
                 map_id: data.map_id,
-                path: data.path.clone(),
+                segments: data.segments.clone(),
                 texture_file: texture.to_string(),
+                display: trail.display.or(marker.display),
+                from_pack: None,
             };
             marker.trails.push(route);
         }
@@ -490,6 +1006,8 @@ impl MarkerPackBuilder {
 mod tests {
     use super::*;
 
+    use crate::parser::filter::Race;
+
     fn marker(name: &str) -> MarkerXml {
         MarkerXml {
             name: name.to_string(),
@@ -497,6 +1015,216 @@ mod tests {
         }
     }
 
+    fn poi(id: &str, guid: Option<PoiGuid>) -> PoiXml {
+        PoiXml {
+            id: id.to_string(),
+            map_id: Some(15),
+            position: Some(Vec3::ONE),
+            icon_file: None,
+            behavior: None,
+            guid,
+            display: Default::default(),
+            filters: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        assert_eq!(MarkerPack::normalize_path("Icons\\A.PNG"), "icons/a.png");
+        assert_eq!(MarkerPack::normalize_path("./a/../b//c.png"), "b/c.png");
+        assert_eq!(MarkerPack::normalize_path("../../a.png"), "a.png");
+        assert_eq!(MarkerPack::resolve_path("a.xml", "b.png"), "b.png");
+        assert_eq!(
+            MarkerPack::resolve_path("data/more/a.xml", "..\\Icons\\b.png"),
+            "data/icons/b.png"
+        );
+    }
+
+    #[test]
+    fn test_poi_guid() {
+        let mut builder = MarkerPackBuilder::new("pack".to_string());
+        let node_id = builder.add_marker(marker("one"));
+        builder.add_poi(poi("one", Some(PoiGuid([1; 16]))), Location::default());
+        builder.add_poi(
+            poi("one", Some(PoiGuid([1; 16]))),
+            Location::new("one.xml", 10),
+        );
+        builder.add_poi(poi("one", None), Location::default());
+        builder.add_poi(poi("one", None), Location::default());
+        let pack = builder.build();
+
+        let pois = &pack.get(node_id).unwrap().data().pois;
+        assert_eq!(pois.len(), 3);
+        assert_eq!(pois[0].guid, PoiGuid([1; 16]));
+        assert_eq!(
+            pois[1].guid,
+            PoiGuid::generate("one", Some(15), Some(Vec3::ONE), 0)
+        );
+        assert_eq!(
+            pois[2].guid,
+            PoiGuid::generate("one", Some(15), Some(Vec3::ONE), 1)
+        );
+
+        let diagnostics = pack.diagnostics().iter().collect::<Vec<_>>();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file, "one.xml");
+        assert_eq!(diagnostics[0].position, Some(10));
+        assert_eq!(
+            diagnostics[0].kind,
+            DiagnosticKind::DuplicateGuid {
+                id: "one".into(),
+                guid: PoiGuid([1; 16])
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_references() {
+        let mut builder = MarkerPackBuilder::new("pack".to_string());
+        builder.add_marker(marker("one"));
+        builder.up();
+        builder.add_poi(poi("two", None), Location::new("pois.xml", 42));
+        builder.add_trail_tag(
+            TrailXml {
+                id: "one".into(),
+                trail_file: "one.trl".into(),
+                texture_file: None,
+                display: Default::default(),
+            },
+            Location::new("trails.xml", 7),
+        );
+        let pack = builder.build();
+
+        let diagnostics = pack.diagnostics();
+        assert!(diagnostics.has_errors());
+        assert_eq!(
+            diagnostics
+                .in_file("pois.xml")
+                .map(|diagnostic| &diagnostic.kind)
+                .collect::<Vec<_>>(),
+            vec![&DiagnosticKind::MissingMarker("two".into())]
+        );
+        assert_eq!(
+            diagnostics
+                .in_file("trails.xml")
+                .map(|diagnostic| &diagnostic.kind)
+                .collect::<Vec<_>>(),
+            vec![&DiagnosticKind::MissingTrailData {
+                id: "one".into(),
+                trail_file: "one.trl".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_inherit_attributes() {
+        let mut builder = MarkerPackBuilder::new("pack".to_string());
+        let a = builder.add_marker(MarkerXml {
+            icon_file: Some(Utf8PathBuf::from("a.png")),
+            behavior: Some(Behavior::DisappearOnUse),
+            display: DisplayAttributes {
+                alpha: Some(0.5),
+                icon_size: Some(1.5),
+                ..Default::default()
+            },
+            filters: Filters {
+                races: Some(vec![Race::Norn]),
+                ..Default::default()
+            },
+            ..marker("a")
+        });
+        let b = builder.add_marker(MarkerXml {
+            icon_file: Some(Utf8PathBuf::from("b.png")),
+            ..marker("b")
+        });
+        let c = builder.add_marker(MarkerXml {
+            display: DisplayAttributes {
+                icon_size: Some(2.0),
+                ..Default::default()
+            },
+            ..marker("c")
+        });
+        builder.add_poi(poi("a.b.c", Some(PoiGuid([1; 16]))), Location::default());
+        builder.add_poi(
+            PoiXml {
+                behavior: Some(Behavior::ReappearDaily),
+                display: DisplayAttributes {
+                    alpha: Some(0.25),
+                    ..Default::default()
+                },
+                filters: Filters {
+                    races: Some(vec![Race::Charr]),
+                    ..Default::default()
+                },
+                ..poi("a.b.c", Some(PoiGuid([2; 16])))
+            },
+            Location::default(),
+        );
+        let pack = builder.build();
+
+        let a = pack.get(a).unwrap().data();
+        assert_eq!(a.icon_file, Some(Utf8PathBuf::from("a.png")));
+        assert_eq!(a.display.icon_size, Some(1.5));
+
+        let b = pack.get(b).unwrap().data();
+        assert_eq!(b.icon_file, Some(Utf8PathBuf::from("b.png")));
+        assert_eq!(b.behavior, Some(Behavior::DisappearOnUse));
+        assert_eq!(b.display.alpha, Some(0.5));
+        assert_eq!(b.display.icon_size, Some(1.5));
+
+        let c = pack.get(c).unwrap().data();
+        assert_eq!(c.icon_file, Some(Utf8PathBuf::from("b.png")));
+        assert_eq!(c.behavior, Some(Behavior::DisappearOnUse));
+        assert_eq!(c.display.alpha, Some(0.5));
+        assert_eq!(c.display.icon_size, Some(2.0));
+
+        let poi = &c.pois[0];
+        assert_eq!(poi.icon_file, Some(Utf8PathBuf::from("b.png")));
+        assert_eq!(poi.behavior, Some(Behavior::DisappearOnUse));
+        assert_eq!(poi.display.alpha, Some(0.5));
+        assert_eq!(poi.display.icon_size, Some(2.0));
+        assert_eq!(poi.filters.races, Some(vec![Race::Norn]));
+
+        let poi = &c.pois[1];
+        assert_eq!(poi.behavior, Some(Behavior::ReappearDaily));
+        assert_eq!(poi.display.alpha, Some(0.25));
+        assert_eq!(poi.display.icon_size, Some(2.0));
+        assert_eq!(poi.filters.races, Some(vec![Race::Charr]));
+    }
+
+    #[test]
+    fn test_redefined_category() {
+        let mut builder = MarkerPackBuilder::new("pack".to_string());
+        let a = builder.add_marker(MarkerXml {
+            display: DisplayAttributes {
+                alpha: Some(0.5),
+                ..Default::default()
+            },
+            ..marker("a")
+        });
+        builder.new_root();
+        builder.add_marker(MarkerXml {
+            behavior: Some(Behavior::DisappearOnUse),
+            display: DisplayAttributes {
+                alpha: Some(1.0),
+                fade_far: Some(100.0),
+                ..Default::default()
+            },
+            ..marker("a")
+        });
+        let b = builder.add_marker(marker("b"));
+        let pack = builder.build();
+
+        let a = pack.get(a).unwrap().data();
+        assert_eq!(a.behavior, Some(Behavior::DisappearOnUse));
+        assert_eq!(a.display.alpha, Some(0.5));
+        assert_eq!(a.display.fade_far, Some(100.0));
+
+        let b = pack.get(b).unwrap().data();
+        assert_eq!(b.behavior, Some(Behavior::DisappearOnUse));
+        assert_eq!(b.display.fade_far, Some(100.0));
+    }
+
     #[test]
     fn test_path_from_string() {
         let mut builder = MarkerPackBuilder::new("pack".to_string());
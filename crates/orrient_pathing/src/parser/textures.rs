@@ -0,0 +1,143 @@
+//! Textures decoded from marker packs on demand.
+//!
+//! Packs only keep their image files encoded. [`PackTextures`] decodes
+//! one the first time a POI or trail that uses it is spawned, and Bevy
+//! frees the decoded image again once nothing holds a handle to it.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use super::pack::MarkerPack;
+use super::MarkerPacks;
+use super::PackId;
+
+/// Decoded textures by pack and file path. Only the [`AssetId`] is kept
+/// so the cache doesn't keep a texture alive by itself.
+#[derive(Resource, Default)]
+struct TextureCache(HashMap<(PackId, String), AssetId<Image>>);
+
+#[derive(SystemParam)]
+pub struct PackTextures<'w> {
+    packs: Res<'w, MarkerPacks>,
+    cache: ResMut<'w, TextureCache>,
+    images: ResMut<'w, Assets<Image>>,
+}
+
+impl PackTextures<'_> {
+    /// The texture at `path` for a POI or trail in a category of
+    /// `pack_id`. When it was added by another pack, `from_pack`, that
+    /// pack is searched first since that's where its own files are.
+    pub fn get(
+        &mut self,
+        pack_id: &PackId,
+        from_pack: Option<&PackId>,
+        path: &str,
+    ) -> Option<Handle<Image>> {
+        from_pack
+            .into_iter()
+            .chain([pack_id])
+            .find_map(|pack_id| self.load(pack_id, path))
+    }
+
+    fn load(&mut self, pack_id: &PackId, path: &str) -> Option<Handle<Image>> {
+        let key = (pack_id.clone(), MarkerPack::normalize_path(path));
+        if let Some(handle) = self
+            .cache
+            .0
+            .get(&key)
+            .and_then(|id| self.images.get_strong_handle(*id))
+        {
+            return Some(handle);
+        }
+
+        let data = self.packs.get(pack_id)?.image_file(&key.1)?;
+        let image = match super::decode_image(&key.1, data) {
+            Ok(image) => image,
+            Err(err) => {
+                warn!("Could not decode {pack_id}/{path}: {err:?}");
+                return None;
+            }
+        };
+        let handle = self.images.add(image);
+        self.cache.0.insert(key, handle.id());
+        Some(handle)
+    }
+}
+
+/// Forget textures once Bevy has freed them.
+fn evict_system(mut events: EventReader<AssetEvent<Image>>, mut cache: ResMut<TextureCache>) {
+    for event in events.read() {
+        if let AssetEvent::Removed { id } = event {
+            cache.0.retain(|_, cached| cached != id);
+        }
+    }
+}
+
+/// Reloaded packs may have changed their images.
+fn clear_system(mut cache: ResMut<TextureCache>) {
+    cache.0.clear();
+}
+
+pub(crate) struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TextureCache>();
+        app.add_systems(
+            Update,
+            (
+                evict_system.run_if(on_event::<AssetEvent<Image>>()),
+                clear_system.run_if(resource_exists_and_changed::<MarkerPacks>),
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine as _;
+    use bevy::ecs::system::RunSystemOnce as _;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_decode_on_demand() {
+        let dir = tempdir().unwrap();
+        let pack_dir = dir.path().join("test");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        // A 1x1 PNG
+        std::fs::write(
+            pack_dir.join("Icon.png"),
+            BASE64_STANDARD
+                .decode("iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==")
+                .unwrap(),
+        )
+        .unwrap();
+
+        let pack_id = PackId("test".into());
+        let mut world = World::new();
+        world.insert_resource(MarkerPacks::new(HashMap::from_iter([(
+            pack_id.clone(),
+            super::super::read_marker_pack(&pack_dir).unwrap(),
+        )])));
+        world.init_resource::<TextureCache>();
+        world.init_resource::<Assets<Image>>();
+
+        // Nothing is decoded until it's asked for.
+        assert_eq!(world.resource::<Assets<Image>>().len(), 0);
+
+        let (first, second, missing) = world.run_system_once(move |mut textures: PackTextures| {
+            (
+                textures.get(&pack_id, None, "icon.png"),
+                textures.get(&pack_id, None, "ICON.png"),
+                textures.get(&pack_id, None, "missing.png"),
+            )
+        });
+        let first = first.unwrap();
+        assert_eq!(Some(first.id()), second.map(|handle| handle.id()));
+        assert!(missing.is_none());
+        assert_eq!(world.resource::<Assets<Image>>().len(), 1);
+    }
+}
@@ -1,20 +1,43 @@
 use anyhow::Result;
 use bevy::math::Vec3;
-use std::{fs::File, io::Read, path::Path};
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+/// A break no longer than this many times a trail's median distance
+/// between points is considered corruption rather than a deliberate
+/// break, such as a jump or a waypoint hop.
+const CORRUPT_GAP_FACTOR: f32 = 2.0;
 
 /// The raw trail data read directly from a file.
 #[derive(Clone, Debug)]
 pub struct TrailData {
-    #[allow(unused)]
     pub version: u32,
     pub map_id: u32,
-    pub path: Vec<Vec3>,
+    /// The disconnected parts of the trail. A `Vec3::ZERO` point in the
+    /// file marks a break between two segments.
+    pub segments: Vec<Vec<Vec3>>,
 }
 
-#[allow(unused)]
-/// Convenience function to try to read a Trail file.
+/// Convenience function to try to read a Trail file. With
+/// `allow_corrupt`, breaks that look like corruption are joined with
+/// [`join_corrupt_segments`].
 pub fn from_file<P: AsRef<Path>>(path: P, allow_corrupt: bool) -> Result<TrailData> {
-    read(File::open(path)?)
+    let mut trail_data = read(File::open(path)?)?;
+    if allow_corrupt {
+        trail_data.segments = join_corrupt_segments(trail_data.segments);
+    }
+    Ok(trail_data)
+}
+
+/// Convenience function to write a Trail file.
+pub fn to_file<P: AsRef<Path>>(path: P, trail_data: &TrailData) -> Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+    write(trail_data, &mut output)?;
+    output.flush()?;
+    Ok(())
 }
 
 /// The main reader function. This will try to parse any reader of
@@ -31,16 +54,20 @@ pub fn read<R: Read>(mut input: R) -> Result<TrailData> {
 
     // The rest of the file are tuples of 3 f32 values. An x, y, and
     // z.
-    let mut path: Vec<Vec3> = vec![];
+    let mut segments: Vec<Vec<Vec3>> = vec![];
+    let mut segment: Vec<Vec3> = vec![];
     loop {
         let mut buf = [0u8; 12];
         if input.read_exact(&mut buf).is_err() {
             // If no bytes are read, then we've reached the end of the
             // file and we can break from the loop.
+            if !segment.is_empty() {
+                segments.push(segment);
+            }
             return Ok(TrailData {
                 version,
                 map_id,
-                path,
+                segments,
             });
         };
 
@@ -48,10 +75,219 @@ pub fn read<R: Read>(mut input: R) -> Result<TrailData> {
         let y = f32::from_le_bytes(buf[4..8].try_into().unwrap());
         let z = f32::from_le_bytes(buf[8..12].try_into().unwrap());
         let pos = Vec3 { x, y, z };
-        // Some of the trail files are corrupt with random Vec3::ZERO
-        // in them. Filter them out to repair the trail here.
-        if pos.length() > 0.0 {
-            path.push(pos);
+        // A zero point starts a new segment. Repeated zeros would only
+        // create empty segments, so they're skipped.
+        if pos == Vec3::ZERO {
+            if !segment.is_empty() {
+                segments.push(std::mem::take(&mut segment));
+            }
+        } else {
+            segment.push(pos);
+        }
+    }
+}
+
+/// Write `trail_data` in the same layout [`read`] expects. A
+/// `Vec3::ZERO` point is written between segments, so points in a
+/// segment must not be zero themselves.
+pub fn write<W: Write>(trail_data: &TrailData, mut output: W) -> Result<()> {
+    output.write_all(&trail_data.version.to_le_bytes())?;
+    output.write_all(&trail_data.map_id.to_le_bytes())?;
+
+    for (i, segment) in trail_data.segments.iter().enumerate() {
+        if i > 0 {
+            write_point(&mut output, Vec3::ZERO)?;
+        }
+        for point in segment {
+            write_point(&mut output, *point)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_point<W: Write>(output: &mut W, point: Vec3) -> Result<()> {
+    for value in point.to_array() {
+        output.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Some trail files are corrupt with random `Vec3::ZERO` points in
+/// them. Join any segments where the gap left behind isn't much longer
+/// than the usual distance between points.
+pub fn join_corrupt_segments(segments: Vec<Vec<Vec3>>) -> Vec<Vec<Vec3>> {
+    let mut steps = segments
+        .iter()
+        .flat_map(|segment| segment.windows(2).map(|pair| pair[0].distance(pair[1])))
+        .collect::<Vec<_>>();
+    if steps.is_empty() {
+        return segments;
+    }
+    steps.sort_by(f32::total_cmp);
+    let max_gap = steps[steps.len() / 2] * CORRUPT_GAP_FACTOR;
+
+    let mut joined: Vec<Vec<Vec3>> = Vec::with_capacity(segments.len());
+    for segment in segments {
+        match joined.last_mut() {
+            Some(last)
+                if last
+                    .last()
+                    .zip(segment.first())
+                    .is_some_and(|(end, start)| end.distance(*start) <= max_gap) =>
+            {
+                last.extend(segment);
+            }
+            _ => joined.push(segment),
+        }
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trail_bytes(points: &[Vec3]) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(15u32.to_le_bytes());
+        for point in points {
+            for value in point.to_array() {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_read_segments() {
+        let bytes = trail_bytes(&[
+            Vec3::ZERO,
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::new(100.0, 0.0, 0.0),
+            Vec3::new(101.0, 0.0, 0.0),
+            Vec3::ZERO,
+        ]);
+        let trail_data = read(bytes.as_slice()).unwrap();
+        assert_eq!(trail_data.map_id, 15);
+        assert_eq!(
+            trail_data.segments,
+            vec![
+                vec![Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0)],
+                vec![Vec3::new(100.0, 0.0, 0.0), Vec3::new(101.0, 0.0, 0.0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_join_corrupt_segments() {
+        let segments = vec![
+            vec![Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 1.0)],
+            // A break no wider than the usual step is corruption.
+            vec![Vec3::new(2.0, 0.0, 1.0), Vec3::new(3.0, 0.0, 1.0)],
+            // A long break is a jump.
+            vec![Vec3::new(50.0, 0.0, 1.0), Vec3::new(51.0, 0.0, 1.0)],
+        ];
+        let joined = join_corrupt_segments(segments);
+        assert_eq!(joined.len(), 2);
+        assert_eq!(joined[0].len(), 4);
+        assert_eq!(joined[1].len(), 2);
+    }
+
+    /// Small deterministic generator so round trips can be checked
+    /// against many random trails.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next_u32(&mut self) -> u32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) as u32
+        }
+
+        fn below(&mut self, max: u32) -> u32 {
+            self.next_u32() % max
+        }
+
+        fn point(&mut self) -> Vec3 {
+            let mut value = || (self.next_u32() as f32 / u32::MAX as f32 - 0.5) * 20000.0;
+            Vec3::new(value(), value(), value())
+        }
+
+        fn trail_data(&mut self) -> TrailData {
+            let segments = (0..self.below(5))
+                .map(|_| {
+                    (0..self.below(20) + 1)
+                        .map(|_| self.point())
+                        .filter(|point| *point != Vec3::ZERO)
+                        .collect::<Vec<_>>()
+                })
+                .filter(|segment| !segment.is_empty())
+                .collect();
+            TrailData {
+                version: self.below(3),
+                map_id: self.next_u32(),
+                segments,
+            }
         }
     }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut rng = Lcg(0x5eed);
+        for _ in 0..500 {
+            let trail_data = rng.trail_data();
+
+            let mut bytes = vec![];
+            write(&trail_data, &mut bytes).unwrap();
+            let read_back = read(bytes.as_slice()).unwrap();
+
+            assert_eq!(read_back.version, trail_data.version);
+            assert_eq!(read_back.map_id, trail_data.map_id);
+            assert_eq!(read_back.segments, trail_data.segments);
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_from_bytes() {
+        // Random files, including repeated, leading and trailing
+        // breaks, come back in their normalized form.
+        let mut rng = Lcg(0xbeef);
+        for _ in 0..500 {
+            let points = (0..rng.below(40))
+                .map(|_| {
+                    if rng.below(4) == 0 {
+                        Vec3::ZERO
+                    } else {
+                        rng.point()
+                    }
+                })
+                .collect::<Vec<_>>();
+            let trail_data = read(trail_bytes(&points).as_slice()).unwrap();
+
+            let mut bytes = vec![];
+            write(&trail_data, &mut bytes).unwrap();
+            let read_back = read(bytes.as_slice()).unwrap();
+            assert_eq!(read_back.segments, trail_data.segments);
+
+            let mut rewritten = vec![];
+            write(&read_back, &mut rewritten).unwrap();
+            assert_eq!(rewritten, bytes);
+        }
+    }
+
+    #[test]
+    fn test_file_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.trl");
+        let trail_data = Lcg(7).trail_data();
+        to_file(&path, &trail_data).unwrap();
+        let read_back = from_file(&path, false).unwrap();
+        assert_eq!(read_back.segments, trail_data.segments);
+    }
 }
@@ -1,9 +1,9 @@
 use orrient_core::prelude::MapId;
+use orrient_pathing::prelude::PoiMapIcon;
 use orrient_pathing::prelude::PoiMarker;
 
 use bevy::color::palettes;
 use bevy::prelude::*;
-use bevy_mod_billboard::BillboardTextureHandle;
 use sickle_ui::prelude::UiContainerExt as _;
 use sickle_ui::ui_builder::UiBuilder;
 use sickle_ui::ui_builder::UiBuilderExt;
@@ -16,12 +16,12 @@ use super::window::CompassWindow;
 pub struct CompassMarker(pub Entity);
 
 impl CompassMarker {
-    fn frame() -> impl Bundle {
+    fn frame(size: f32) -> impl Bundle {
         NodeBundle {
             style: Style {
                 position_type: PositionType::Absolute,
-                width: Val::Px(16.),
-                height: Val::Px(16.),
+                width: Val::Px(size),
+                height: Val::Px(size),
                 ..default()
             },
             background_color: palettes::basic::RED.into(),
@@ -31,17 +31,17 @@ impl CompassMarker {
 }
 
 pub trait UiCompassMarkerExt {
-    fn compass_marker(&mut self, entity: Entity, image: Handle<Image>);
+    fn compass_marker(&mut self, entity: Entity, image: Handle<Image>, size: f32);
 }
 
 impl UiCompassMarkerExt for UiBuilder<'_, Entity> {
-    fn compass_marker(&mut self, entity: Entity, icon: Handle<Image>) {
-        let mut builder = self.container(CompassMarker::frame(), |parent| {
+    fn compass_marker(&mut self, entity: Entity, icon: Handle<Image>, size: f32) {
+        let mut builder = self.container(CompassMarker::frame(size), |parent| {
             parent.insert(ImageBundle {
                 image: icon.into(),
                 style: Style {
-                    width: Val::Px(16.),
-                    height: Val::Px(16.),
+                    width: Val::Px(size),
+                    height: Val::Px(size),
                     ..default()
                 },
                 ..default()
@@ -54,13 +54,15 @@ impl UiCompassMarkerExt for UiBuilder<'_, Entity> {
 fn spawn_marker(
     trigger: Trigger<OnAdd, PoiMarker>,
     mut commands: Commands,
-    q_images: Query<&BillboardTextureHandle>,
+    q_icons: Query<&PoiMapIcon>,
     q_compass: Query<Entity, With<CompassWindow>>,
 ) {
-    if let Ok(billboard) = q_images.get(trigger.entity()) {
-        commands
-            .ui_builder(q_compass.single())
-            .compass_marker(trigger.entity(), billboard.0.clone());
+    if let Ok(map_icon) = q_icons.get(trigger.entity()) {
+        commands.ui_builder(q_compass.single()).compass_marker(
+            trigger.entity(),
+            map_icon.icon.clone(),
+            map_icon.size,
+        );
     } else {
         warn!("No icon for compass marker found.")
     }
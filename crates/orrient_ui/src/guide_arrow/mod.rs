@@ -24,33 +24,15 @@ fn draw(mut gizmos: Gizmos, player: Query<&Transform, With<Player>>, closest: Re
 fn update(
     mut closest: ResMut<Closest>,
     player: Query<&Transform, With<Player>>,
-    map_markers: Res<MapMarkers>,
-    enabled_markers: Res<EnabledMarkers>,
-    packs: Res<MarkerPacks>,
+    index: SpatialIndex,
 ) {
     let Ok(player) = player.get_single() else {
         return;
     };
-    let pos = player.translation;
-    closest.0 = enabled_markers
-        .intersection(&map_markers)
-        .filter_map(|full_id| {
-            packs.get(&full_id.pack_id).and_then(|pack| {
-                pack.find_by_name(full_id.marker_name.clone())
-                    .and_then(|node_id| pack.get(node_id))
-                    .map(|node| node.data())
-                    .map(|marker| marker.pois.clone())
-            })
-        })
-        .flatten()
-        .filter_map(|poi| poi.position)
-        .reduce(|a, b| {
-            if a.distance(pos) < b.distance(pos) {
-                a
-            } else {
-                b
-            }
-        });
+    closest.0 = index
+        .nearest_n(player.translation, 1, SpatialEntry::is_poi)
+        .first()
+        .map(|nearby| nearby.point);
 }
 
 pub(crate) struct Plugin;